@@ -0,0 +1,294 @@
+use std::io::Cursor;
+
+use quick_xml::events::{BytesEnd, BytesStart, BytesText, Event};
+use quick_xml::Writer;
+
+use crate::render::{attribute, heading_slug};
+
+/// Wrap images standing alone in a paragraph into numbered figures captioned with their title or
+/// alt text, with ids unique among `used` anchors. All images are loaded lazily.
+pub fn add_figures(html: &str, used: &[String]) -> (String, Vec<String>) {
+    let mut reader = quick_xml::reader::Reader::from_str(html);
+    let mut writer = Writer::new(Cursor::new(Vec::new()));
+    let mut figures: Vec<String> = vec![];
+    // start tag and inner events of the paragraph being read
+    let mut paragraph: Option<(BytesStart<'static>, Vec<Event<'static>>)> = None;
+    let lazy = |e: &BytesStart| {
+        let mut img = e.to_owned();
+        img.clear_attributes();
+        img.extend_attributes(e.attributes().flatten()
+            .filter(|attr| !matches!(attr.key.0, b"loading" | b"decoding")));
+        img.push_attribute(("loading", "lazy"));
+        img.push_attribute(("decoding", "async"));
+        img.into_owned()
+    };
+    loop {
+        match reader.read_event() {
+            Ok(Event::Start(e)) if paragraph.is_none() && e.name().0 == b"p" => {
+                paragraph = Some((e.into_owned(), vec![]));
+            }
+            Ok(Event::End(e)) if paragraph.is_some() && e.name().0 == b"p" => {
+                let (start, events) = paragraph.take().unwrap();
+                let mut content = events.iter()
+                    .filter(|e| !matches!(e, Event::Text(t) if t.iter().all(u8::is_ascii_whitespace)));
+                match (content.next(), content.next()) {
+                    (Some(Event::Empty(img)), None) if img.name().0 == b"img" => {
+                        let used: Vec<_> = used.iter().chain(&figures).cloned().collect();
+                        let id = heading_slug(&format!("figure-{}", figures.len() + 1), &used);
+                        let caption = attribute(img, b"title").or_else(|| attribute(img, b"alt")).unwrap_or_default();
+                        let mut figure = BytesStart::new("figure");
+                        figure.push_attribute(("id", id.as_str()));
+                        writer.write_event(Event::Start(figure)).unwrap();
+                        writer.write_event(Event::Empty(lazy(img))).unwrap();
+                        writer.write_event(Event::Start(BytesStart::new("figcaption"))).unwrap();
+                        let mut number = BytesStart::new("span");
+                        number.push_attribute(("class", "figure-number"));
+                        writer.write_event(Event::Start(number)).unwrap();
+                        writer.write_event(Event::Text(BytesText::new(&format!("Figure {}", figures.len() + 1)))).unwrap();
+                        writer.write_event(Event::End(BytesEnd::new("span"))).unwrap();
+                        if !caption.is_empty() {
+                            writer.write_event(Event::Text(BytesText::new(&format!(" {caption}")))).unwrap();
+                        }
+                        writer.write_event(Event::End(BytesEnd::new("figcaption"))).unwrap();
+                        writer.write_event(Event::End(BytesEnd::new("figure"))).unwrap();
+                        figures.push(id);
+                    }
+                    _ => {
+                        writer.write_event(Event::Start(start)).unwrap();
+                        for event in events {
+                            match event {
+                                Event::Empty(img) if img.name().0 == b"img" => writer.write_event(Event::Empty(lazy(&img))).unwrap(),
+                                event => writer.write_event(event).unwrap(),
+                            }
+                        }
+                        writer.write_event(Event::End(e)).unwrap();
+                    }
+                }
+            }
+            Ok(Event::Empty(e)) if paragraph.is_none() && e.name().0 == b"img" => {
+                writer.write_event(Event::Empty(lazy(&e))).unwrap();
+            }
+            Ok(Event::Eof) => break,
+            Ok(e) => match &mut paragraph {
+                Some((_, events)) => events.push(e.into_owned()),
+                None => writer.write_event(e).unwrap(),
+            },
+            Err(_) => panic!("Fail to parse html"),
+        }
+    }
+    (String::from_utf8(writer.into_inner().into_inner()).unwrap(), figures)
+}
+
+/// Kind of GitHub style admonitions, with its title and icon.
+type Admonition = (&'static str, &'static str, &'static str);
+
+const ADMONITIONS: [Admonition; 5] = [
+    ("NOTE", "Note", "fa-circle-info"),
+    ("TIP", "Tip", "fa-lightbulb"),
+    ("IMPORTANT", "Important", "fa-circle-exclamation"),
+    ("WARNING", "Warning", "fa-triangle-exclamation"),
+    ("CAUTION", "Caution", "fa-circle-xmark"),
+];
+
+fn write_events(events: Vec<Event<'static>>) -> String {
+    let mut writer = Writer::new(Cursor::new(Vec::new()));
+    for event in events {
+        writer.write_event(event).unwrap();
+    }
+    String::from_utf8(writer.into_inner().into_inner()).unwrap()
+}
+
+/// The admonition of a blockquote starting with `[!NOTE]` alone on its line, and its content
+/// without the marker.
+fn admonition(mut events: Vec<Event<'static>>) -> Option<(&'static Admonition, Vec<Event<'static>>)> {
+    let first = events.iter().position(|e| !matches!(e, Event::Text(t) if t.iter().all(u8::is_ascii_whitespace)))?;
+    if !matches!(&events[first], Event::Start(p) if p.name().0 == b"p") {
+        return None
+    }
+    let Some(Event::Text(text)) = events.get(first + 1) else { return None };
+    let text = text.unescape().ok()?.into_owned();
+    let (kind, rest) = text.strip_prefix("[!")?.split_once(']')?;
+    let admonition = ADMONITIONS.iter().find(|(name, _, _)| name.eq_ignore_ascii_case(kind))?;
+    if !rest.is_empty() && !rest.starts_with('\n') {
+        return None
+    }
+    let rest = rest.trim_start();
+    if rest.is_empty() && matches!(events.get(first + 2), Some(Event::End(_))) {
+        // the marker is a paragraph of its own
+        events.drain(first..first + 3);
+    } else {
+        events[first + 1] = Event::Text(BytesText::new(rest).into_owned());
+    }
+    Some((admonition, events))
+}
+
+/// Turn blockquotes starting with `[!NOTE]`, `[!TIP]`, `[!IMPORTANT]`, `[!WARNING]` or
+/// `[!CAUTION]` into titled admonitions.
+pub fn add_admonitions(html: &str) -> String {
+    let mut reader = quick_xml::reader::Reader::from_str(html);
+    let mut writer = Writer::new(Cursor::new(Vec::new()));
+    // start tag, inner events and depth of nested blockquotes of the blockquote being read
+    let mut quote: Option<(BytesStart<'static>, Vec<Event<'static>>, usize)> = None;
+    loop {
+        match reader.read_event() {
+            Ok(Event::Start(e)) if e.name().0 == b"blockquote" => match &mut quote {
+                Some((_, events, depth)) => {
+                    *depth += 1;
+                    events.push(Event::Start(e.into_owned()));
+                }
+                None => quote = Some((e.into_owned(), vec![], 0)),
+            },
+            Ok(Event::End(e)) if e.name().0 == b"blockquote" && quote.is_some() => {
+                if let Some((_, events, depth)) = &mut quote && *depth > 0 {
+                    *depth -= 1;
+                    events.push(Event::End(e.into_owned()));
+                    continue
+                }
+                let (start, events) = quote.take().map(|(start, events, _)| (start, events)).unwrap();
+                match admonition(events.clone()) {
+                    Some(((name, title, icon), events)) => {
+                        let mut aside = BytesStart::new("aside");
+                        aside.push_attribute(("class", format!("admonition admonition-{}", name.to_lowercase()).as_str()));
+                        writer.write_event(Event::Start(aside)).unwrap();
+                        writer.write_event(Event::Text(BytesText::from_escaped(format!(
+                            r#"<p class="admonition-title"><i class="fa-solid {icon}" aria-hidden="true"></i> {title}</p>"#
+                        )))).unwrap();
+                        writer.write_event(Event::Text(BytesText::from_escaped(add_admonitions(&write_events(events))))).unwrap();
+                        writer.write_event(Event::End(BytesEnd::new("aside"))).unwrap();
+                    }
+                    None => {
+                        writer.write_event(Event::Start(start)).unwrap();
+                        writer.write_event(Event::Text(BytesText::from_escaped(add_admonitions(&write_events(events))))).unwrap();
+                        writer.write_event(Event::End(e)).unwrap();
+                    }
+                }
+            }
+            Ok(Event::Eof) => break,
+            Ok(e) => match &mut quote {
+                Some((_, events, _)) => events.push(e.into_owned()),
+                None => writer.write_event(e).unwrap(),
+            },
+            Err(_) => panic!("Fail to parse html"),
+        }
+    }
+    String::from_utf8(writer.into_inner().into_inner()).unwrap()
+}
+
+/// Whether every element opened in a fragment of html is closed in it.
+fn balanced(fragment: &str) -> bool {
+    let mut reader = quick_xml::reader::Reader::from_str(fragment);
+    let mut depth = 0usize;
+    loop {
+        match reader.read_event() {
+            Ok(Event::Start(_)) => depth += 1,
+            Ok(Event::End(_)) if depth == 0 => return false,
+            Ok(Event::End(_)) => depth -= 1,
+            Ok(Event::Eof) => return depth == 0,
+            Ok(_) => {},
+            Err(_) => return false,
+        }
+    }
+}
+
+/// Turn paragraphs of lines of terms followed by lines of definitions starting with `: ` into
+/// definition lists, leaving any other paragraph alone. Adjacent lists are merged.
+pub fn add_definition_lists(html: &str) -> String {
+    let mut reader = quick_xml::reader::Reader::from_str(html);
+    let mut writer = Writer::new(Cursor::new(Vec::new()));
+    let mut paragraph: Option<(BytesStart<'static>, Vec<Event<'static>>)> = None;
+    loop {
+        match reader.read_event() {
+            Ok(Event::Start(e)) if paragraph.is_none() && e.name().0 == b"p" => {
+                paragraph = Some((e.into_owned(), vec![]));
+            }
+            Ok(Event::End(e)) if paragraph.is_some() && e.name().0 == b"p" => {
+                let (start, events) = paragraph.take().unwrap();
+                let content = write_events(events);
+                let lines: Vec<_> = content.split('\n').collect();
+                let terms = lines.iter().take_while(|line| !line.starts_with(": ")).count();
+                let is_list = terms > 0 && terms < lines.len()
+                    && lines[..terms].iter().all(|line| !line.trim().is_empty())
+                    && lines[terms..].iter().all(|line| line.starts_with(": "))
+                    && lines.iter().all(|line| balanced(line));
+                if is_list {
+                    let items: Vec<_> = lines.iter().map(|line| match line.strip_prefix(": ") {
+                        Some(definition) => format!("<dd>{definition}</dd>"),
+                        None => format!("<dt>{line}</dt>"),
+                    }).collect();
+                    writer.write_event(Event::Text(BytesText::from_escaped(format!("<dl>\n{}\n</dl>", items.join("\n"))))).unwrap();
+                } else {
+                    writer.write_event(Event::Start(start)).unwrap();
+                    writer.write_event(Event::Text(BytesText::from_escaped(content))).unwrap();
+                    writer.write_event(Event::End(e)).unwrap();
+                }
+            }
+            Ok(Event::Eof) => break,
+            Ok(e) => match &mut paragraph {
+                Some((_, events)) => events.push(e.into_owned()),
+                None => writer.write_event(e).unwrap(),
+            },
+            Err(_) => panic!("Fail to parse html"),
+        }
+    }
+    String::from_utf8(writer.into_inner().into_inner()).unwrap().replace("</dl>\n<dl>\n", "")
+}
+
+/// Ids of footnote references and footnotes, which are linked to each other.
+pub fn footnote_ids(html: &str) -> Vec<String> {
+    let mut reader = quick_xml::reader::Reader::from_str(html);
+    let mut ids = vec![];
+    let mut in_footnotes = false;
+    loop {
+        match reader.read_event() {
+            Ok(Event::Start(e)) => {
+                if e.name().0 == b"section" && attribute(&e, b"data-footnotes").is_some() {
+                    in_footnotes = true;
+                    continue
+                }
+                let linked = match e.name().0 {
+                    b"li" => in_footnotes,
+                    b"a" => attribute(&e, b"data-footnote-ref").is_some(),
+                    _ => false,
+                };
+                if linked && let Some(id) = attribute(&e, b"id") {
+                    ids.push(id);
+                }
+            }
+            Ok(Event::End(e)) if e.name().0 == b"section" => in_footnotes = false,
+            Ok(Event::Eof) | Err(_) => break,
+            Ok(_) => {},
+        }
+    }
+    ids
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::render::render;
+
+    #[test]
+    fn test_figures() {
+        let rendered = render("# Figure 1\n\n![A cat](cat.png)\n\nInline ![dog](dog.png) image.\n\n![](plot.png \"Results\")\n");
+        assert_eq!(rendered.headings, ["figure-1", "figure-1-1", "figure-2"]);
+        assert!(rendered.html.contains(r#"<figure id="figure-1-1"><img src="cat.png" alt="A cat" loading="lazy" decoding="async"/><figcaption><span class="figure-number">Figure 1</span> A cat</figcaption></figure>"#));
+        assert!(rendered.html.contains(r#"<p>Inline <img src="dog.png" alt="dog" loading="lazy" decoding="async"/> image.</p>"#));
+        assert!(rendered.html.contains(r#"<span class="figure-number">Figure 2</span> Results</figcaption>"#));
+    }
+
+    #[test]
+    fn test_blocks() {
+        let rendered = render("> [!TIP]\n> Use **this**.\n\n> [!warning]\n>\n> > [!NOTE]\n> > Nested.\n\n> [!NOTE] not alone\n\nApple\n: A fruit.\n\nPear\n: Another.\n\nNot *a\n: list*\n\nA wrapped\n: paragraph goes\non and\n: on.\n\nSee[^a].\n\n[^a]: Note.\n");
+        assert!(rendered.html.starts_with(concat!(
+            r#"<aside class="admonition admonition-tip"><p class="admonition-title"><i class="fa-solid fa-lightbulb" aria-hidden="true"></i> Tip</p>"#,
+            "\n<p>Use <strong>this</strong>.</p>\n</aside>\n",
+            r#"<aside class="admonition admonition-warning"><p class="admonition-title"><i class="fa-solid fa-triangle-exclamation" aria-hidden="true"></i> Warning</p>"#,
+            "\n\n",
+            r#"<aside class="admonition admonition-note">"#,
+        )));
+        assert!(rendered.html.contains("<blockquote>\n<p>[!NOTE] not alone</p>\n</blockquote>"));
+        assert!(rendered.html.contains("<dl>\n<dt>Apple</dt>\n<dd>A fruit.</dd>\n<dt>Pear</dt>\n<dd>Another.</dd>\n</dl>"));
+        assert!(rendered.html.contains("<p>Not <em>a\n: list</em></p>"));
+        assert!(rendered.html.contains("<p>A wrapped\n: paragraph goes\non and\n: on.</p>"));
+        assert_eq!(rendered.headings, ["footnote-label", "user-content-fnref-a", "user-content-fn-a"]);
+    }
+}
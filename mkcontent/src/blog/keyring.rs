@@ -0,0 +1,73 @@
+use std::collections::BTreeMap;
+use std::{error::Error, fs, path::Path};
+
+use serde::Deserialize;
+
+use crate::crypto;
+
+/// Prefix of environment variables of passwords.
+const VARIABLE_PREFIX: &str = "MKCONTENT_PASSWORD_";
+
+/// Passwords by name in `keyring.toml` of the content directory, which shall be kept out of
+/// version control. Blogs refer to them by name, so that a reader group shares one password.
+/// Names not in the file are looked up in environment variables, see `Keyring::variable`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(transparent)]
+pub struct Keyring(pub BTreeMap<String, String>);
+
+impl Keyring {
+    /// Load passwords from file, an absent file has no password.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Box<dyn Error>> {
+        if !fs::exists(&path)? {
+            return Ok(Self::default())
+        }
+        Ok(toml::from_str(&fs::read_to_string(path)?)?)
+    }
+    /// Environment variable of a password, e.g. `MKCONTENT_PASSWORD_FAMILY` for `family`.
+    pub fn variable(name: &str) -> String {
+        let name: String = name.chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+            .collect();
+        format!("{VARIABLE_PREFIX}{name}")
+    }
+    /// The password of a name, from the file or else the environment.
+    pub fn get(&self, name: &str) -> Result<String, String> {
+        if let Some(password) = self.0.get(name) {
+            return Ok(password.clone())
+        }
+        let variable = Self::variable(name);
+        std::env::var(&variable)
+            .map_err(|_| format!("password \"{name}\" is found neither in keyring.toml nor in ${variable}"))
+    }
+    /// Fingerprint of passwords in the file and the environment, which changes with any of them.
+    pub fn fingerprint(&self, previous: Option<&str>) -> String {
+        let variables = std::env::vars_os()
+            .filter_map(|(name, value)| Some((name.into_string().ok()?, value.into_string().ok()?)));
+        self.fingerprint_with(variables, previous)
+    }
+    fn fingerprint_with(&self, variables: impl Iterator<Item = (String, String)>, previous: Option<&str>) -> String {
+        let variables: BTreeMap<_, _> = variables.filter(|(name, _)| name.starts_with(VARIABLE_PREFIX)).collect();
+        crypto::fingerprint(&serde_json::to_string(&(&self.0, variables)).unwrap(), previous)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fingerprint() {
+        let keyring = Keyring([("family".to_string(), "fam".to_string())].into());
+        let variables = |value: &str| [
+            ("MKCONTENT_PASSWORD_CLASS".to_string(), value.to_string()),
+            ("HOME".to_string(), "/root".to_string()),
+        ].into_iter();
+        let fingerprint = keyring.fingerprint_with(variables("old"), None);
+        let previous = Some(fingerprint.as_str());
+        assert_eq!(keyring.fingerprint_with(variables("old"), previous), fingerprint);
+        assert_ne!(Keyring::default().fingerprint_with(variables("old"), previous), fingerprint);
+        // passwords only in the environment are rotated as well
+        assert_ne!(keyring.fingerprint_with(variables("new"), previous), fingerprint);
+        assert_eq!(keyring.fingerprint_with(variables("old").take(1), previous), fingerprint);
+    }
+}
@@ -1,15 +1,32 @@
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use serde::de::{self, Visitor};
 use std::fmt;
+use schemars::JsonSchema;
+use ts_rs::TS;
 
-#[derive(Debug, PartialEq)]
+/// Creative Commons license, written as its canonical name like `CC BY 4.0`.
+#[derive(Debug, PartialEq, TS, JsonSchema)]
 pub enum License {
+    #[ts(rename = "CC BY 4.0")]
+    #[schemars(rename = "CC BY 4.0")]
     CcBy4_0,
+    #[ts(rename = "CC BY-NC 4.0")]
+    #[schemars(rename = "CC BY-NC 4.0")]
     CcByNc4_0,
+    #[ts(rename = "CC BY-NC-ND 4.0")]
+    #[schemars(rename = "CC BY-NC-ND 4.0")]
     CcByNcNd4_0,
+    #[ts(rename = "CC BY-NC-SA 4.0")]
+    #[schemars(rename = "CC BY-NC-SA 4.0")]
     CcByNcSa4_0,
+    #[ts(rename = "CC BY-ND 4.0")]
+    #[schemars(rename = "CC BY-ND 4.0")]
     CcByNd4_0,
+    #[ts(rename = "CC BY-SA 4.0")]
+    #[schemars(rename = "CC BY-SA 4.0")]
     CcBySa4_0,
+    #[ts(rename = "CC0 1.0")]
+    #[schemars(rename = "CC0 1.0")]
     Cc01_0,
 }
 impl License {
@@ -81,7 +98,7 @@ impl License {
             format!(", and each are © {year} by {creator}.\n")
         };
         
-        return format!(r#"{preamble}
+        format!(r#"{preamble}
 
 {canonical_name} ("this {license_type}") applies to all files contained in this archive
 including its subdirectories ("these files"), including text, images, and other media .
@@ -97,7 +114,7 @@ impl Serialize for License {
         S: Serializer,
     {
         let s = self.canonical_name();
-        serializer.serialize_str(&s)
+        serializer.serialize_str(s)
     }
 }
 
@@ -119,7 +136,7 @@ impl<'de> Deserialize<'de> for License {
             where
                 E: de::Error,
             {
-                License::parse(value).map_err(|e| E::custom(e))
+                License::parse(value).map_err(|e| E::custom(format!("unknown license \"{e}\", expected a license string like 'CC BY 4.0'")))
             }
         }
 
@@ -1,13 +1,192 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
 
 use serde::{Serialize, Deserialize};
+use serde_json::Value;
+use ts_rs::TS;
+
+use crate::migration::Versioned;
 
 use super::Blog;
+use super::tag::TagMetadata;
+
+/// Maximum number of related blogs listed under a blog.
+const RELATED_LIMIT: usize = 5;
 
+/// Links from a blog to other public blogs, calculated after the manifest is updated.
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq, TS)]
+pub struct Navigation {
+    /// The previous public blog in chronological order.
+    pub prev: Option<String>,
+    /// The next public blog in chronological order.
+    pub next: Option<String>,
+    /// Public blogs sharing most tags with the blog.
+    #[serde(default)]
+    pub related: Vec<String>,
+}
 
+impl Navigation {
+    /// Ids of all blogs linked.
+    pub fn links(&self) -> impl Iterator<Item = &String> {
+        self.prev.iter().chain(self.next.iter()).chain(self.related.iter())
+    }
+}
 
+/// Anchors and references of a blog, which are only needed to build, so they are kept in the cache
+/// rather than the published manifest.
 #[derive(Debug, Default, Serialize, Deserialize)]
+pub struct BlogLinks {
+    pub headings: Option<Vec<String>>,
+    pub references: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, TS)]
 pub struct BlogManifest {
-    pub blogs: HashMap<String, Blog>,
-    pub tags: HashMap<String, HashSet<String>>,
+    pub version: u32,
+    /// Blogs keyed by id, ordered so that identical content yields identical manifests.
+    #[ts(type = "Record<string, Blog>")]
+    pub blogs: BTreeMap<String, Blog>,
+    #[ts(type = "Record<string, Array<string>>")]
+    pub tags: BTreeMap<String, BTreeSet<String>>,
+    #[serde(default)]
+    #[ts(type = "Record<string, Array<string>>")]
+    pub series: BTreeMap<String, BTreeSet<String>>,
+    /// Canonical tags declared in `tags.toml`.
+    #[serde(default)]
+    #[ts(type = "Record<string, TagMetadata>")]
+    pub tag_metadata: BTreeMap<String, TagMetadata>,
+}
+
+impl Default for BlogManifest {
+    fn default() -> Self {
+        Self {
+            version: Self::VERSION,
+            blogs: BTreeMap::new(),
+            tags: BTreeMap::new(),
+            series: BTreeMap::new(),
+            tag_metadata: BTreeMap::new(),
+        }
+    }
+}
+
+impl Versioned for BlogManifest {
+    const VERSION: u32 = 1;
+    fn migrate(version: u32, mut value: Value) -> Result<Value, Box<dyn Error>> {
+        match version {
+            // series, navigation and tag metadata are introduced with defaults.
+            0 => {
+                value.as_object_mut().ok_or("manifest is not an object")?.insert("version".to_string(), 1.into());
+                Ok(value)
+            },
+            _ => Err("unknown version".into()),
+        }
+    }
+}
+
+impl BlogManifest {
+    /// Anchors and references of every blog.
+    pub fn links(&self) -> BTreeMap<&str, BlogLinks> {
+        self.blogs.iter().map(|(id, blog)| (id.as_str(), BlogLinks {
+            headings: blog.headings.clone(),
+            references: blog.references.clone(),
+        })).collect()
+    }
+
+    /// Restore anchors and references of blogs from the cache.
+    pub fn set_links(&mut self, mut links: BTreeMap<String, BlogLinks>) {
+        for (id, blog) in &mut self.blogs {
+            let BlogLinks { headings, references } = links.remove(id).unwrap_or_default();
+            blog.headings = headings;
+            blog.references = references;
+        }
+    }
+
+    /// Blogs in a series, ordered by `series_order` and then by creation time.
+    pub fn series_blogs(&self, name: &str) -> Vec<(&str, &Blog)> {
+        let mut blogs: Vec<_> = self.series.get(name).into_iter().flatten()
+            .filter_map(|id| self.blogs.get_key_value(id))
+            .map(|(id, blog)| (id.as_str(), blog))
+            .collect();
+        blogs.sort_by(|(a_id, a), (b_id, b)| {
+            (a.series_order.is_none(), a.series_order, a.created, a_id)
+                .cmp(&(b.series_order.is_none(), b.series_order, b.created, b_id))
+        });
+        blogs
+    }
+
+    /// Calculate navigation of every blog. Blogs with password are never linked.
+    pub fn navigation(&self) -> BTreeMap<String, Navigation> {
+        let mut public: Vec<_> = self.blogs.iter().filter(|(_, blog)| !blog.password).collect();
+        public.sort_by_key(|(id, blog)| (blog.created, *id));
+
+        self.blogs.iter().map(|(id, blog)| {
+            // position of the blog if inserted in the public timeline
+            let position = public.partition_point(|(other_id, other)| (other.created, *other_id) < (blog.created, id));
+            let prev = position.checked_sub(1).map(|i| public[i].0.clone());
+            let next = public[position..].iter()
+                .find(|(other_id, _)| *other_id != id)
+                .map(|(other_id, _)| (*other_id).clone());
+
+            let mut related: Vec<_> = public.iter()
+                .filter(|(other_id, _)| *other_id != id)
+                .map(|(other_id, other)| (other.tags.iter().filter(|tag| blog.tags.contains(tag)).count(), *other_id, *other))
+                .filter(|(shared, _, _)| *shared > 0)
+                .collect();
+            related.sort_by(|(a_shared, a_id, a), (b_shared, b_id, b)| {
+                b_shared.cmp(a_shared).then(b.created.cmp(&a.created)).then(a_id.cmp(b_id))
+            });
+            let related = related.into_iter().take(RELATED_LIMIT).map(|(_, other_id, _)| other_id.clone()).collect();
+
+            (id.clone(), Navigation { prev, next, related })
+        }).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::OffsetDateTime;
+
+    fn blog(title: &str, tags: &[&str], password: bool, day: i64) -> Blog {
+        let created = OffsetDateTime::from_unix_timestamp(day * 86400).unwrap();
+        Blog {
+            password,
+            hint: None,
+            title: title.to_string(),
+            language: Default::default(),
+            license: None,
+            tags: tags.iter().map(|tag| tag.to_string()).collect(),
+            series: None,
+            series_order: None,
+            created,
+            modified: created,
+            preview: None,
+            navigation: Navigation::default(),
+            headings: None,
+            references: vec![],
+            keep_image_metadata: false,
+            remember: false,
+            literal_password: false,
+            private: None,
+        }
+    }
+
+    #[test]
+    fn test_navigation() {
+        let mut manifest = BlogManifest::default();
+        manifest.blogs.insert("a.md".into(), blog("A", &["rust"], false, 1));
+        manifest.blogs.insert("b.md".into(), blog("B", &["rust", "web"], true, 2));
+        manifest.blogs.insert("c.md".into(), blog("C", &["web"], false, 3));
+        manifest.blogs.insert("d.md".into(), blog("D", &["rust", "web"], false, 4));
+
+        let navigation = manifest.navigation();
+        assert_eq!(navigation["a.md"].prev, None);
+        assert_eq!(navigation["a.md"].next.as_deref(), Some("c.md"));
+        // blogs with password are skipped, but still have links
+        assert_eq!(navigation["b.md"].prev.as_deref(), Some("a.md"));
+        assert_eq!(navigation["b.md"].next.as_deref(), Some("c.md"));
+        assert_eq!(navigation["d.md"].next, None);
+        assert_eq!(navigation["b.md"].related, ["d.md", "c.md", "a.md"]);
+        assert_eq!(navigation["c.md"].related, ["d.md"]);
+    }
 }
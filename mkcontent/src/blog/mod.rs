@@ -1,13 +1,22 @@
 use std::{error::Error, fmt::Display, fs, io::{BufRead, BufReader}, path::Path, time::SystemTime};
 
 use license::License;
+use manifest::Navigation;
+use keyring::Keyring;
+use tag::TagRegistry;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use ts_rs::TS;
 use time::OffsetDateTime;
 use toml::value::Datetime;
+pub mod keyring;
 pub mod manifest;
 pub mod license;
+pub mod shard;
+pub mod tag;
 
-#[derive(Debug, Serialize, Deserialize, PartialEq)]
+/// BCP 47 language tag of the blog content.
+#[derive(Debug, Serialize, Deserialize, PartialEq, TS, JsonSchema)]
 pub struct Language(String);
 
 impl Default for Language {
@@ -21,56 +30,138 @@ impl Display for Language {
     }
 }
 
+/// One password, or several to share a blog with different readers.
+#[derive(Deserialize, JsonSchema)]
+#[serde(untagged)]
+enum Passwords {
+    One(String),
+    Many(Vec<String>),
+}
+
 /// build blog info from toml
-#[derive(Deserialize)]
+#[derive(Deserialize, JsonSchema)]
+#[schemars(title = "Blog frontmatter", description = "TOML frontmatter between `---` lines of a blog.")]
 struct BlogBuilder {
-    password: Option<String>,
+    /// Password to encrypt the blog with, or a list of them for different readers, each unlocking it.
+    /// Prefer `password_ref`, since anyone reading the source of the blog can read the password.
+    password: Option<Passwords>,
+    /// Name of the password in `keyring.toml` of the content directory, or else in the environment
+    /// variable `MKCONTENT_PASSWORD_<NAME>`, which keeps it out of the blog.
+    password_ref: Option<String>,
+    /// Names of more passwords like `password_ref`, which unlock the blog as well.
+    #[serde(default)]
+    keys: Vec<String>,
+    /// Hint shown above the password input.
     hint: Option<String>,
     title: String,
     #[serde(default)]
     language: Language,
+    /// Creative Commons license of the blog, all rights are reserved without it.
     license: Option<License>,
     #[serde(default)]
     tags: Vec<String>,
+    /// TOML date or date-time, defaults to the file creation time.
+    #[schemars(with = "Option<String>")]
     created: Option<Datetime>,
+    /// Name of the multi-part series the blog belongs to.
+    series: Option<String>,
+    /// Position in the series.
+    series_order: Option<u32>,
+    /// Publish images in assets with their metadata, such as location and camera.
+    #[serde(default)]
+    keep_image_metadata: bool,
+    /// Publish a blog with password under a placeholder title without tags and series, with the
+    /// real ones restored after it is unlocked.
+    #[serde(default)]
+    private_metadata: bool,
+    /// Let readers remember the password on their device, so that other blogs with the same
+    /// password unlock without typing it again. This publishes an id of each password which is
+    /// the same in every blog with it, revealing which blogs share a password.
+    #[serde(default)]
+    remember: bool,
 }
+/// JSON Schema of blog frontmatter.
+pub fn frontmatter_schema() -> schemars::Schema {
+    crate::schema::toml_schema::<BlogBuilder>()
+}
+
 impl BlogBuilder {
     fn new(input: &str) -> Result<Self, toml::de::Error> {
         toml::from_str::<Self>(input)
     }
-    /// Return the blog and password as Option<String>
-    fn build(self, os_created: OffsetDateTime, os_modified: OffsetDateTime) -> Result<(Blog, Option<String>), &'static str> {
-        Ok((Blog {
-            password: self.password.is_some(),
+    /// Return the blog and its passwords, which are empty without password.
+    fn build(self, os_created: OffsetDateTime, os_modified: OffsetDateTime, tags: &TagRegistry, keyring: &Keyring) -> Result<(Blog, Vec<String>), Box<dyn Error>> {
+        let literal_password = self.password.is_some();
+        let mut passwords = match self.password {
+            Some(Passwords::One(password)) => vec![password],
+            Some(Passwords::Many(passwords)) => passwords,
+            None => vec![],
+        };
+        for name in self.password_ref.iter().chain(&self.keys) {
+            passwords.push(keyring.get(name)?);
+        }
+        let mut seen = std::collections::HashSet::new();
+        passwords.retain(|password| seen.insert(password.clone()));
+        if passwords.len() > crate::crypto::MAX_PASSWORDS {
+            return Err(format!("at most {} passwords can unlock a blog", crate::crypto::MAX_PASSWORDS).into())
+        }
+        if self.private_metadata && passwords.is_empty() {
+            return Err("private_metadata requires a password".into())
+        }
+        let private_metadata = self.private_metadata;
+        let mut blog = Blog {
+            password: !passwords.is_empty(),
             hint: self.hint,
             title: self.title,
             language: self.language,
             license: self.license,
-            tags: self.tags,
-            created: self.created.map_or(Ok(os_created),
+            tags: tags.normalize(self.tags),
+            series: self.series,
+            series_order: self.series_order,
+            created: self.created.map_or(Ok::<_, &str>(os_created),
                 |toml_time| {
-                    let date = toml_time.date.map(|toml::value::Date{year, month, day}|
-                        time::Date::from_calendar_date(year as i32, time::Month::try_from(month).unwrap(), day).unwrap()).ok_or("No date when parsing created time")?;
-                    let time = toml_time.time.map_or(time::Time::from_hms(0, 0, 0).unwrap(),
-                        |toml::value::Time{hour, minute, second, nanosecond}| time::Time::from_hms_nano(hour, minute, second, nanosecond).unwrap());
-                    let offset = toml_time.offset.map(|offset| {
-                        match offset {
-                            toml::value::Offset::Z => time::UtcOffset::UTC,
-                            toml::value::Offset::Custom { minutes } => time::UtcOffset::from_whole_seconds(minutes as i32 * 60).unwrap()
-                        }
-                    }).unwrap_or(time::UtcOffset::current_local_offset().unwrap());
+                    let toml::value::Date{year, month, day} = toml_time.date.ok_or("No date when parsing created time")?;
+                    let date = time::Month::try_from(month).ok()
+                        .and_then(|month| time::Date::from_calendar_date(year as i32, month, day).ok())
+                        .ok_or("Invalid date when parsing created time")?;
+                    let time = toml_time.time.map_or(Ok(time::Time::MIDNIGHT),
+                        |toml::value::Time{hour, minute, second, nanosecond}| time::Time::from_hms_nano(hour, minute, second, nanosecond))
+                        .map_err(|_| "Invalid time when parsing created time")?;
+                    let offset = match toml_time.offset {
+                        Some(toml::value::Offset::Z) => time::UtcOffset::UTC,
+                        Some(toml::value::Offset::Custom { minutes }) => time::UtcOffset::from_whole_seconds(minutes as i32 * 60)
+                            .map_err(|_| "Invalid offset when parsing created time")?,
+                        None => time::UtcOffset::current_local_offset().map_err(|_| "Cannot determine local offset for created time")?,
+                    };
                     Ok(time::OffsetDateTime::new_in_offset(date, time, offset))
                 }
             )?,
             // modified is only used in os modified.
             modified: os_modified,
             preview: None,
-        }, self.password))
+            navigation: Navigation::default(),
+            headings: None,
+            references: vec![],
+            keep_image_metadata: self.keep_image_metadata,
+            remember: self.remember,
+            literal_password,
+            private: None,
+        };
+        if private_metadata {
+            blog.private = Some(PrivateMetadata {
+                title: std::mem::replace(&mut blog.title, PRIVATE_TITLE.to_string()),
+                language: std::mem::take(&mut blog.language),
+                tags: std::mem::take(&mut blog.tags),
+                series: blog.series.take(),
+            });
+            blog.series_order = None;
+        }
+        Ok((blog, passwords))
     }
 }
 
 /// After building, will only be serialize and deserialize by json.
-#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Serialize, Deserialize, PartialEq, TS)]
 pub struct Blog {
     pub password: bool,
     pub hint: Option<String>,
@@ -80,49 +171,182 @@ pub struct Blog {
     pub license: Option<License>,
     #[serde(default)]
     pub tags: Vec<String>,
+    /// Name of the multi-part series the blog belongs to.
+    #[serde(default)]
+    pub series: Option<String>,
+    /// Position in the series, blogs without it are ordered by creation time.
+    #[serde(default)]
+    pub series_order: Option<u32>,
+    /// RFC 3339 date.
     #[serde(with = "time::serde::rfc3339")]
+    #[ts(type = "string")]
     pub created: OffsetDateTime,
+    /// RFC 3339 date.
     #[serde(with = "time::serde::rfc3339")]
+    #[ts(type = "string")]
     pub modified: OffsetDateTime,
     pub preview: Option<String>,
+    #[serde(default)]
+    pub navigation: Navigation,
+    /// Anchors of headings, figures and footnotes, unknown for blogs with password. Kept in the
+    /// cache with `references` rather than the manifest, see `BlogLinks`.
+    #[serde(skip)]
+    #[ts(skip)]
+    pub headings: Option<Vec<String>>,
+    /// Ids of other blogs linked in the content.
+    #[serde(skip)]
+    #[ts(skip)]
+    pub references: Vec<String>,
+    /// Only known when the blog is parsed.
+    #[serde(skip)]
+    #[ts(skip)]
+    pub keep_image_metadata: bool,
+    /// Whether readers may remember the password, only known when the blog is parsed.
+    #[serde(skip)]
+    #[ts(skip)]
+    pub remember: bool,
+    /// Whether the password is written in the frontmatter, only known when the blog is read.
+    #[serde(skip)]
+    #[ts(skip)]
+    pub literal_password: bool,
+    /// Real metadata replaced by placeholders, only known when the blog is parsed.
+    #[serde(skip)]
+    #[ts(skip)]
+    pub private: Option<PrivateMetadata>,
 }
 
-impl Blog {
-    pub fn parse(path: impl AsRef<Path>, os_created: SystemTime, os_modified: SystemTime) -> Result<(Self, Option<String>), Box<dyn Error>> {
-
-        let file = fs::File::open(path)?;
-        let file_reader = BufReader::new(file);
-        let mut file_metadata = String::new();
-        let mut in_frontmatter = false;
-        for line in file_reader.lines() {
-            let line = line?;
-            if line.trim() == "---" {
-                if in_frontmatter {
-                    let builder = BlogBuilder::new(&file_metadata)?;
-                    return Ok(builder.build(os_created.into(), os_modified.into())?)
-                } else {
-                    in_frontmatter = true;
-                }
+/// Title of blogs with private metadata.
+pub const PRIVATE_TITLE: &str = "Protected blog";
+
+/// Metadata of a blog with password hidden from the public, encrypted along with its content.
+#[derive(Debug, PartialEq)]
+pub struct PrivateMetadata {
+    pub title: String,
+    pub language: Language,
+    pub tags: Vec<String>,
+    pub series: Option<String>,
+}
+
+/// Read the toml frontmatter between `---` lines of a blog file.
+pub fn read_frontmatter(path: impl AsRef<Path>) -> Result<String, Box<dyn Error>> {
+    let file = fs::File::open(path)?;
+    let file_reader = BufReader::new(file);
+    let mut file_metadata = String::new();
+    let mut in_frontmatter = false;
+    for line in file_reader.lines() {
+        let line = line?;
+        if line.trim() == "---" {
+            if in_frontmatter {
+                return Ok(file_metadata)
             } else {
-                file_metadata.push_str(&line);
-                file_metadata.push('\n');
+                in_frontmatter = true;
             }
+        } else {
+            file_metadata.push_str(&line);
+            file_metadata.push('\n');
         }
-        return Err(Box::new(std::io::Error::new(
-            std::io::ErrorKind::InvalidData,
-            "No frontmatter found in blog file",
-        )));
     }
-    /// Return if the blog is updated.
-    pub fn update(&mut self, path: impl AsRef<Path>, os_created: SystemTime, os_modified: SystemTime, regenerate: bool) -> Result<Option<Option<String>>, Box<dyn Error>> {
+    Err(Box::new(std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        "No frontmatter found in blog file",
+    )))
+}
+
+impl Blog {
+    /// Return the blog and its passwords, which are empty without password.
+    pub fn parse(path: impl AsRef<Path>, os_created: SystemTime, os_modified: SystemTime, tags: &TagRegistry, keyring: &Keyring) -> Result<(Self, Vec<String>), Box<dyn Error>> {
+        let builder = BlogBuilder::new(&read_frontmatter(path)?)?;
+        builder.build(os_created.into(), os_modified.into(), tags, keyring)
+    }
+    /// Return passwords if the blog is updated.
+    pub fn update(&mut self, path: impl AsRef<Path>, os_created: SystemTime, os_modified: SystemTime, tags: &TagRegistry, keyring: &Keyring, regenerate: bool) -> Result<Option<Vec<String>>, Box<dyn Error>> {
         if !regenerate && os_modified <= self.modified {
+            // not kept in the manifest, so that whether the blog is warned about is read again
+            self.literal_password = BlogBuilder::new(&read_frontmatter(path)?)?.password.is_some();
             return Ok(None)
         }
-        let (blog, pwd) = Self::parse(path, os_created, os_modified)?;
+        let (blog, passwords) = Self::parse(path, os_created, os_modified, tags, keyring)?;
         *self = blog;
-        Ok(Some(pwd))
+        Ok(Some(passwords))
     }
     pub fn get_copyright_year(&self) -> i32 {
         self.created.year()
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+    use manifest::BlogManifest;
+
+    #[test]
+    fn test_private_metadata() {
+        let build = |frontmatter: &str| BlogBuilder::new(frontmatter).unwrap()
+            .build(OffsetDateTime::UNIX_EPOCH, OffsetDateTime::UNIX_EPOCH, &TagRegistry::default(), &Keyring::default());
+
+        let (blog, password) = build("title = \"Trip\"\nlanguage = \"zh-CN\"\ntags = [\"travel\"]\nseries = \"Diary\"\nseries_order = 2\npassword = \"pw\"\nprivate_metadata = true").unwrap();
+        assert_eq!(password, ["pw"]);
+        assert_eq!((blog.title.as_str(), blog.language.to_string()), (PRIVATE_TITLE, "en-US".to_string()));
+        assert!(blog.tags.is_empty() && blog.series.is_none() && blog.series_order.is_none());
+        assert_eq!(blog.private, Some(PrivateMetadata {
+            title: "Trip".to_string(),
+            language: Language("zh-CN".to_string()),
+            tags: vec!["travel".to_string()],
+            series: Some("Diary".to_string()),
+        }));
+
+        assert!(build("title = \"Trip\"\nprivate_metadata = true").is_err());
+    }
+
+    #[test]
+    fn test_references() {
+        let build = |frontmatter: &str| BlogBuilder::new(frontmatter).unwrap()
+            .build(OffsetDateTime::UNIX_EPOCH, OffsetDateTime::UNIX_EPOCH, &TagRegistry::default(), &Keyring::default()).unwrap().0;
+
+        // anchors and links are only kept in the cache, not in the published manifest
+        let mut blog = build("title = \"Trip\"\npassword = \"pw\"");
+        blog.headings = Some(vec!["setup".to_string()]);
+        blog.references = vec!["a.md".to_string()];
+        let value = serde_json::to_value(&blog).unwrap();
+        assert!(value.get("headings").is_none() && value.get("references").is_none());
+
+        let mut manifest = BlogManifest::default();
+        manifest.blogs.insert("b.md".to_string(), blog);
+        let links = serde_json::to_string(&manifest.links()).unwrap();
+        let mut manifest: BlogManifest = serde_json::from_str(&serde_json::to_string(&manifest).unwrap()).unwrap();
+        assert!(manifest.blogs["b.md"].references.is_empty());
+        manifest.set_links(serde_json::from_str(&links).unwrap());
+        assert_eq!(manifest.blogs["b.md"].headings.as_deref(), Some(["setup".to_string()].as_slice()));
+        assert_eq!(manifest.blogs["b.md"].references, ["a.md"]);
+    }
+
+    #[test]
+    fn test_password_ref() {
+        let keyring = Keyring([("family".to_string(), "fam".to_string())].into());
+        let build = |frontmatter: &str| BlogBuilder::new(frontmatter).unwrap()
+            .build(OffsetDateTime::UNIX_EPOCH, OffsetDateTime::UNIX_EPOCH, &TagRegistry::default(), &keyring);
+
+        let (blog, passwords) = build("title = \"Trip\"\npassword_ref = \"family\"").unwrap();
+        assert_eq!(passwords, ["fam"]);
+        assert!(blog.password && !blog.literal_password && !blog.remember);
+        assert!(build("title = \"Trip\"\npassword_ref = \"family\"\nremember = true").unwrap().0.remember);
+
+        let (blog, passwords) = build("title = \"Trip\"\npassword = [\"pw\", \"fam\"]\nkeys = [\"family\"]").unwrap();
+        assert_eq!(passwords, ["pw", "fam"]);
+        assert!(blog.literal_password);
+
+        // the manifest does not have it, so it is read again even if the blog is not updated
+        let dir = TempDir::new("blog");
+        let path = dir.join("trip.md");
+        fs::write(&path, "---\ntitle = \"Trip\"\npassword = \"pw\"\n---\n").unwrap();
+        let modified = fs::metadata(&path).unwrap().modified().unwrap();
+        let (blog, _) = Blog::parse(&path, modified, modified, &TagRegistry::default(), &keyring).unwrap();
+        let mut blog: Blog = serde_json::from_str(&serde_json::to_string(&blog).unwrap()).unwrap();
+        assert!(!blog.literal_password);
+        assert_eq!(blog.update(&path, modified, modified, &TagRegistry::default(), &keyring, false).unwrap(), None);
+        assert!(blog.literal_password);
+
+        let error = build("title = \"Trip\"\npassword_ref = \"mkcontent test missing\"").unwrap_err();
+        assert!(error.to_string().contains("$MKCONTENT_PASSWORD_MKCONTENT_TEST_MISSING"));
+    }
+}
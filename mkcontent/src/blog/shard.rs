@@ -0,0 +1,97 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use serde::Serialize;
+use ts_rs::TS;
+
+use super::manifest::BlogManifest;
+use super::tag::tag_slug;
+use super::Blog;
+
+/// A blog in listing files, with its id.
+#[derive(Debug, Serialize, TS)]
+pub struct ListedBlog<'a> {
+    pub id: &'a str,
+    #[serde(flatten)]
+    pub blog: &'a Blog,
+}
+
+/// A page of blogs, newest first.
+#[derive(Debug, Serialize, TS)]
+pub struct BlogPage<'a> {
+    /// Page number, starting from 1.
+    pub page: usize,
+    pub pages: usize,
+    pub blogs: Vec<ListedBlog<'a>>,
+}
+
+/// Page counts of a listing.
+#[derive(Debug, Serialize, TS)]
+pub struct ListingIndex {
+    /// Directory of page files relative to the api directory.
+    pub path: String,
+    pub total: usize,
+    pub pages: usize,
+}
+
+/// Describes every listing, written to `blogs/index.json`.
+#[derive(Debug, Serialize, TS)]
+pub struct ShardIndex {
+    pub page_size: usize,
+    pub blogs: ListingIndex,
+    #[ts(type = "Record<string, ListingIndex>")]
+    pub tags: BTreeMap<String, ListingIndex>,
+}
+
+/// Files of the sharded API, as paths relative to the api directory and contents.
+#[derive(Debug)]
+pub struct Shards<'a> {
+    pub index: ShardIndex,
+    pub pages: Vec<(PathBuf, BlogPage<'a>)>,
+    pub blogs: Vec<(PathBuf, &'a Blog)>,
+}
+
+/// Split blogs into pages, returning the listing index.
+fn paginate<'a>(mut blogs: Vec<ListedBlog<'a>>, page_size: usize, path: String, pages: &mut Vec<(PathBuf, BlogPage<'a>)>) -> ListingIndex {
+    blogs.sort_by(|a, b| b.blog.created.cmp(&a.blog.created).then(a.id.cmp(b.id)));
+    let total = blogs.len();
+    let page_count = total.div_ceil(page_size);
+    let mut blogs = blogs.into_iter();
+    for page in 1..=page_count {
+        pages.push((PathBuf::from(&path).join(format!("page-{page}.json")), BlogPage {
+            page,
+            pages: page_count,
+            blogs: blogs.by_ref().take(page_size).collect(),
+        }));
+    }
+    ListingIndex { path, total, pages: page_count }
+}
+
+impl BlogManifest {
+    /// Shard the manifest into paginated listings of all blogs and of every tag,
+    /// and a metadata file per blog in `posts`.
+    pub fn shards(&self, page_size: usize) -> Shards<'_> {
+        let page_size = page_size.max(1);
+        let listed = |ids: &mut dyn Iterator<Item = &String>| -> Vec<ListedBlog> {
+            ids.filter_map(|id| self.blogs.get_key_value(id))
+                .map(|(id, blog)| ListedBlog { id, blog })
+                .collect()
+        };
+        let mut pages = vec![];
+
+        let blogs = paginate(listed(&mut self.blogs.keys()), page_size, "blogs".to_string(), &mut pages);
+        let mut tags = BTreeMap::new();
+        for (tag, ids) in &self.tags {
+            let path = format!("tags/{}", tag_slug(tag));
+            tags.insert(tag.clone(), paginate(listed(&mut ids.iter()), page_size, path, &mut pages));
+        }
+
+        Shards {
+            index: ShardIndex { page_size, blogs, tags },
+            pages,
+            blogs: self.blogs.iter()
+                .map(|(id, blog)| (PathBuf::from("posts").join(format!("{}.json", id.strip_suffix(".md").unwrap_or(id))), blog))
+                .collect(),
+        }
+    }
+}
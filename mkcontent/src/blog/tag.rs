@@ -0,0 +1,119 @@
+use std::collections::BTreeMap;
+use std::{error::Error, fs, path::Path};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use ts_rs::TS;
+
+/// File system safe name of a tag, used by static pages and API files. Tags with anything but
+/// lowercase alphanumerics, `-` and `_` get a hash of the tag after `~`, which is never kept, so
+/// that "C++" and "C#", or "Rust" and "rust" on case-insensitive file systems, don't share a name.
+pub fn tag_slug(tag: &str) -> String {
+    let slug: String = tag.chars()
+        .flat_map(char::to_lowercase)
+        .map(|c| if c.is_alphanumeric() || c == '-' || c == '_' { c } else { '-' })
+        .collect();
+    if slug == tag {
+        return slug
+    }
+    let hash: String = Sha256::digest(tag.as_bytes())[..4].iter().map(|b| format!("{b:02x}")).collect();
+    format!("{slug}~{hash}")
+}
+
+/// Tags sharing a slug case-insensitively, whose pages would overwrite each other.
+pub fn slug_clashes<'a>(tags: impl IntoIterator<Item = &'a str>) -> Vec<Vec<&'a str>> {
+    let mut slugs: BTreeMap<String, Vec<&str>> = BTreeMap::new();
+    for tag in tags {
+        let clashing = slugs.entry(tag_slug(tag).to_lowercase()).or_default();
+        if !clashing.contains(&tag) {
+            clashing.push(tag);
+        }
+    }
+    slugs.into_values().filter(|tags| tags.len() > 1).collect()
+}
+
+/// Metadata of a canonical tag declared in `tags.toml`.
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq, TS)]
+pub struct TagMetadata {
+    /// Name shown to readers, defaults to the canonical tag.
+    pub name: Option<String>,
+    pub description: Option<String>,
+    /// Any CSS color.
+    pub color: Option<String>,
+    /// Other spellings normalized to the canonical tag.
+    #[serde(default)]
+    pub aliases: Vec<String>,
+}
+
+/// Canonical tags declared in `tags.toml`, keyed by the canonical tag.
+#[derive(Debug, Default, Deserialize)]
+#[serde(transparent)]
+pub struct TagRegistry(pub BTreeMap<String, TagMetadata>);
+
+impl TagRegistry {
+    /// Load tags from file, an absent file declares no tags.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Box<dyn Error>> {
+        if !fs::exists(&path)? {
+            return Ok(Self::default())
+        }
+        Ok(toml::from_str(&fs::read_to_string(path)?)?)
+    }
+    /// The canonical tag of a tag or alias, compared case-insensitively.
+    pub fn canonical(&self, tag: &str) -> Option<&str> {
+        let tag = tag.trim().to_lowercase();
+        self.0.iter()
+            .find(|(canonical, metadata)| {
+                canonical.to_lowercase() == tag || metadata.aliases.iter().any(|alias| alias.to_lowercase() == tag)
+            })
+            .map(|(canonical, _)| canonical.as_str())
+    }
+    /// Normalize tags to canonical ones without duplicates. Unknown tags are kept as written.
+    pub fn normalize(&self, tags: Vec<String>) -> Vec<String> {
+        let mut normalized: Vec<String> = vec![];
+        for tag in tags {
+            let tag = self.canonical(&tag).map_or_else(|| tag.trim().to_string(), str::to_string);
+            if !tag.is_empty() && !normalized.contains(&tag) {
+                normalized.push(tag);
+            }
+        }
+        normalized
+    }
+    /// Tags not declared, only reported when any tag is declared.
+    pub fn unknown<'a>(&self, tags: &'a [String]) -> Vec<&'a str> {
+        if self.0.is_empty() {
+            return vec![]
+        }
+        tags.iter().filter(|tag| !self.0.contains_key(*tag)).map(String::as_str).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize() {
+        let registry: TagRegistry = toml::from_str(r#"
+            [rust]
+            name = "Rust"
+            aliases = ["rust-lang"]
+        "#).unwrap();
+        let tags = ["Rust", " rust-lang", "web", "RUST", ""].map(String::from).to_vec();
+        let normalized = registry.normalize(tags);
+        assert_eq!(normalized, ["rust", "web"]);
+        assert_eq!(registry.unknown(&normalized), ["web"]);
+        assert!(TagRegistry::default().unknown(&normalized).is_empty());
+    }
+
+    #[test]
+    fn test_tag_slug() {
+        assert_eq!(tag_slug("rust-lang"), "rust-lang");
+        assert!(tag_slug("Web Dev").starts_with("web-dev~"));
+        let tags = ["C++", "C#", "c--", "Rust", "rust", "RUST"];
+        let slugs: Vec<_> = tags.iter().map(|tag| tag_slug(tag)).collect();
+        for (i, slug) in slugs.iter().enumerate() {
+            assert!(slugs[i + 1..].iter().all(|other| !other.eq_ignore_ascii_case(slug)), "{slug} is shared");
+        }
+        assert!(slug_clashes(tags.into_iter().chain(["rust"])).is_empty());
+    }
+}
@@ -0,0 +1,238 @@
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::{fs, path::Path};
+
+use crate::blog::manifest::BlogManifest;
+use crate::blog::keyring::Keyring;
+use crate::blog::tag::{slug_clashes, TagRegistry};
+use crate::blog::{read_frontmatter, Blog};
+use crate::render;
+use crate::site::Site;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// A problem found in content.
+#[derive(Debug)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// Path relative to the content directory.
+    pub file: String,
+    pub message: String,
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(f, "{severity}: {}: {}", self.file, self.message)
+    }
+}
+
+/// Path relative to the blogs directory an url refers to, which is where both blogs and
+/// their rendered pages are. External urls, anchors and other absolute paths are skipped.
+fn local_target(url: &str) -> Option<String> {
+    if url.is_empty() || url.starts_with('#') || url.starts_with("//") || url.contains(':') {
+        return None
+    }
+    let path = url.split(['#', '?']).next().unwrap_or_default();
+    let path = match path.strip_prefix("/blogs/") {
+        Some(path) => path,
+        None if path.starts_with('/') => return None,
+        None => path,
+    };
+    Some(render::percent_decode(path))
+}
+
+/// Check blogs and sites in the content directory without writing anything.
+pub fn check(input: impl AsRef<Path>) -> Vec<Diagnostic> {
+    let blogs_path = input.as_ref().join("blogs");
+    let sites_path = input.as_ref().join("sites");
+    let mut diagnostics = vec![];
+    let mut report = |severity, file: &str, message: String| diagnostics.push(Diagnostic {
+        severity,
+        file: file.to_string(),
+        message,
+    });
+
+    let tag_registry = TagRegistry::load(input.as_ref().join("tags.toml")).unwrap_or_else(|e| {
+        report(Severity::Error, "tags.toml", e.to_string());
+        TagRegistry::default()
+    });
+    let keyring = Keyring::load(input.as_ref().join("keyring.toml")).unwrap_or_else(|e| {
+        report(Severity::Error, "keyring.toml", e.to_string());
+        Keyring::default()
+    });
+
+    let mut ids: Vec<_> = fs::read_dir(&blogs_path).into_iter().flatten().flatten()
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .filter(|id| id.ends_with(".md"))
+        .collect();
+    ids.sort();
+
+    let mut titles: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    let mut tagged: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    // blogs are rendered before links between them are validated
+    let mut manifest = BlogManifest::default();
+    let mut rendered_blogs = vec![];
+    for id in &ids {
+        let file = format!("blogs/{id}");
+        let path = blogs_path.join(id);
+
+        // normalized tags of parsed blogs have empty tags removed
+        if let Ok(frontmatter) = read_frontmatter(&path)
+            && let Ok(toml::Value::Table(table)) = toml::from_str(&frontmatter)
+            && let Some(toml::Value::Array(tags)) = table.get("tags")
+            && tags.iter().any(|tag| tag.as_str().is_some_and(|tag| tag.trim().is_empty())) {
+            report(Severity::Error, &file, "empty tag".to_string());
+        }
+
+        let parsed = fs::metadata(&path).map_err(Into::into).and_then(|metadata| {
+            Blog::parse(&path, metadata.created()?, metadata.modified()?, &tag_registry, &keyring)
+        });
+        let mut blog = match parsed {
+            Ok((blog, _)) => blog,
+            Err(e) => {
+                report(Severity::Error, &file, format!("invalid frontmatter: {e}"));
+                continue
+            }
+        };
+        let tags = blog.private.as_ref().map_or(&blog.tags, |private| &private.tags);
+        for tag in tag_registry.unknown(tags) {
+            report(Severity::Warning, &file, format!("tag \"{tag}\" is not declared in tags.toml"));
+        }
+        for tag in tags {
+            tagged.entry(tag.clone()).or_default().push(id.clone());
+        }
+        if blog.literal_password {
+            report(Severity::Warning, &file, "password is written in the frontmatter, use password_ref instead".to_string());
+        }
+        let title = blog.private.as_ref().map_or(&blog.title, |private| &private.title);
+        titles.entry(title.clone()).or_default().push(id.clone());
+
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) => {
+                report(Severity::Error, &file, e.to_string());
+                continue
+            }
+        };
+        let rendered = render::render(&content);
+        for diagnostic in &rendered.diagnostics {
+            report(Severity::Error, &file, diagnostic.clone());
+        }
+        for secret in &rendered.secrets {
+            match (&secret.password_ref, &secret.password) {
+                (Some(name), _) => if let Err(e) = keyring.get(name) {
+                    report(Severity::Error, &file, e);
+                },
+                (None, Some(_)) => report(Severity::Warning, &file, "password of a secret section is written in the blog, use password_ref instead".to_string()),
+                (None, None) => report(Severity::Error, &file, "secret section has no password_ref".to_string()),
+            }
+        }
+        for image in &rendered.images {
+            if let Some(target) = local_target(image)
+                && !blogs_path.join(&target).is_file() {
+                report(Severity::Error, &file, format!("missing asset {image}"));
+            }
+        }
+        for link in &rendered.links {
+            let Some(target) = local_target(link) else { continue };
+            // generated listing pages
+            if target.starts_with("tags/") || target.starts_with("archive/") {
+                continue
+            }
+            let exists = match target.strip_suffix(".html") {
+                Some(stem) => blogs_path.join(format!("{stem}.md")).is_file(),
+                // links to blogs are validated when resolved
+                None => target.ends_with(".md") || blogs_path.join(&target).exists(),
+            };
+            if !exists {
+                report(Severity::Error, &file, format!("broken link {link}"));
+            }
+        }
+        // anchors of blogs with password are checked as well
+        blog.headings = Some(rendered.headings.clone());
+        manifest.blogs.insert(id.clone(), blog);
+        rendered_blogs.push((id, file, rendered));
+    }
+    for (id, file, rendered) in rendered_blogs {
+        let headings: Vec<_> = rendered.headings.iter().chain(rendered.secrets.iter().flat_map(|secret| &secret.headings)).cloned().collect();
+        let htmls = [&rendered.html].into_iter().chain(rendered.secrets.iter().map(|secret| &secret.html));
+        for html in htmls {
+            for diagnostic in render::resolve_links(html, id, &headings, &manifest).diagnostics {
+                report(Severity::Error, &file, diagnostic);
+            }
+        }
+    }
+    for (title, ids) in titles {
+        if ids.len() > 1 {
+            for id in &ids {
+                report(Severity::Error, &format!("blogs/{id}"), format!("title \"{title}\" is shared by {}", ids.join(", ")));
+            }
+        }
+    }
+
+    for tags in slug_clashes(tagged.keys().map(String::as_str)) {
+        for tag in &tags {
+            let others: Vec<_> = tags.iter().filter(|other| *other != tag).copied().collect();
+            for id in &tagged[*tag] {
+                report(Severity::Error, &format!("blogs/{id}"), format!("tag \"{tag}\" has the same file name as \"{}\"", others.join("\", \"")));
+            }
+        }
+    }
+
+    let mut sites: Vec<_> = fs::read_dir(&sites_path).into_iter().flatten().flatten()
+        .filter(|entry| entry.file_type().is_ok_and(|ty| ty.is_dir()))
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .collect();
+    sites.sort();
+    for id in sites {
+        let file = format!("sites/{id}/site.toml");
+        let site = fs::read_to_string(sites_path.join(&id).join("site.toml"))
+            .map_err(|e| e.to_string())
+            .and_then(|s| toml::from_str::<Site>(&s).map_err(|e| e.to_string()));
+        if let Err(e) = site {
+            report(Severity::Error, &file, e);
+        }
+    }
+    diagnostics
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    #[test]
+    fn test_local_target() {
+        assert_eq!(local_target("foo.assets/a%20b.png").as_deref(), Some("foo.assets/a b.png"));
+        assert_eq!(local_target("/blogs/foo.html#intro").as_deref(), Some("foo.html"));
+        assert_eq!(local_target("bar.html?x=1").as_deref(), Some("bar.html"));
+        assert_eq!(local_target("/sites/demo/"), None);
+        assert_eq!(local_target("https://example.com/a.png"), None);
+        assert_eq!(local_target("mailto:me@example.com"), None);
+        assert_eq!(local_target("#heading"), None);
+    }
+
+    #[test]
+    fn test_private_titles() {
+        let dir = TempDir::new("check");
+        fs::create_dir_all(dir.join("blogs")).unwrap();
+        let private = |title: &str| format!("---\ntitle = \"{title}\"\npassword = \"pw\"\nprivate_metadata = true\n---\nSecret.\n");
+        fs::write(dir.join("blogs").join("c.md"), private("Trip")).unwrap();
+        fs::write(dir.join("blogs").join("d.md"), private("Diary")).unwrap();
+
+        // placeholders of private blogs are not taken as shared titles
+        let shared = |diagnostics: Vec<Diagnostic>| diagnostics.iter().filter(|d| d.message.contains("is shared by")).count();
+        assert_eq!(shared(check(&dir)), 0);
+
+        fs::write(dir.join("blogs").join("d.md"), private("Trip")).unwrap();
+        assert_eq!(shared(check(&dir)), 2);
+    }
+}
@@ -1,61 +1,364 @@
-use aes::cipher::{BlockEncryptMut, KeyIvInit};
+use aes::cipher::{BlockDecryptMut, BlockEncryptMut, KeyIvInit};
 use aes::Aes256;
 use cbc::cipher::block_padding::Pkcs7;
-use password_hash::rand_core::RngCore;
+use hmac::{Hmac, Mac};
+use password_hash::rand_core::{self, impls, RngCore};
 use pbkdf2::password_hash::rand_core::OsRng;
 use pbkdf2::pbkdf2_hmac;
-use sha2::Sha256;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet};
+use std::{fs, io, path::Path};
 type Aes256CbcEnc = cbc::Encryptor<Aes256>;
+type Aes256CbcDec = cbc::Decryptor<Aes256>;
+type HmacSha256 = Hmac<Sha256>;
 const ROUNDS: u32 = 100000;
 
-pub fn encrypt_data(plaintext: &str, password: &str) -> Vec<u8> {
-    // Generate a random salt
-    let mut salt = [0u8; 16];
-    OsRng.fill_bytes(&mut salt);
+/// At most this many passwords unlock a blog, whose count is kept in one byte.
+pub const MAX_PASSWORDS: usize = u8::MAX as usize;
 
-    // 2. Derive key (32 bytes) + IV (16 bytes)
+fn encrypt_cbc(key: &[u8; 32], iv: &[u8; 16], plaintext: &[u8]) -> Vec<u8> {
+    Aes256CbcEnc::new(key.into(), iv.into()).encrypt_padded_vec_mut::<Pkcs7>(plaintext)
+}
+
+fn decrypt_cbc(key: &[u8; 32], iv: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>, &'static str> {
+    let decryptor = Aes256CbcDec::new_from_slices(key, iv).map_err(|_| "Invalid IV")?;
+    decryptor.decrypt_padded_vec_mut::<Pkcs7>(ciphertext).map_err(|_| "Decryption failed")
+}
+
+/// Derive key (32 bytes) + IV (16 bytes) from a password.
+fn derive(password: &str, salt: &[u8; 16]) -> ([u8; 32], [u8; 16]) {
     let mut key_iv = [0u8; 48];
-    pbkdf2_hmac::<Sha256>(password.as_bytes(), &salt, ROUNDS, &mut key_iv);
+    pbkdf2_hmac::<Sha256>(password.as_bytes(), salt, ROUNDS, &mut key_iv);
+    (key_iv[..32].try_into().unwrap(), key_iv[32..].try_into().unwrap())
+}
+
+/// Fingerprint of secrets telling whether any of them changes, derived as slowly as keys so that
+/// it is no shortcut to guess them. It starts with its random salt, reused from `previous` if any.
+pub fn fingerprint(secrets: &str, previous: Option<&str>) -> String {
+    use base64::prelude::*;
+    let salt = previous.and_then(|previous| BASE64_STANDARD.decode(previous).ok())
+        .and_then(|bytes| bytes.get(..16)?.try_into().ok())
+        .unwrap_or_else(|| random_seed()[..16].try_into().unwrap());
+    let (key, _) = derive(secrets, &salt);
+    BASE64_STANDARD.encode([salt.as_slice(), &key].concat())
+}
+
+/// HMAC of length-prefixed parts, so that different parts never give the same input.
+fn hmac(key: &[u8], parts: &[&[u8]]) -> [u8; 32] {
+    let mut mac = HmacSha256::new_from_slice(key).unwrap();
+    for part in parts {
+        mac.update(&(part.len() as u64).to_le_bytes());
+        mac.update(part);
+    }
+    mac.finalize().into_bytes().into()
+}
+
+/// Generator of the same bytes for the same seed and context, so that a blog is encrypted the same
+/// way until its content or passwords change.
+pub struct SeededRng {
+    key: [u8; 32],
+    counter: u64,
+}
+
+impl SeededRng {
+    pub fn new(seed: &[u8], context: &[&[u8]]) -> Self {
+        Self { key: hmac(seed, context), counter: 0 }
+    }
+}
+
+impl RngCore for SeededRng {
+    fn next_u32(&mut self) -> u32 {
+        impls::next_u32_via_fill(self)
+    }
+    fn next_u64(&mut self) -> u64 {
+        impls::next_u64_via_fill(self)
+    }
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(32) {
+            let block = hmac(&self.key, &[&self.counter.to_le_bytes()]);
+            self.counter += 1;
+            chunk.copy_from_slice(&block[..chunk.len()]);
+        }
+    }
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+pub fn random_seed() -> [u8; 32] {
+    let mut seed = [0u8; 32];
+    OsRng.fill_bytes(&mut seed);
+    seed
+}
+
+/// Read the seed of `SeededRng` kept between builds, generating it if absent.
+pub fn load_seed(path: &Path) -> io::Result<[u8; 32]> {
+    if fs::exists(path)? {
+        return fs::read(path)?.try_into()
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "seed shall be 32 bytes"))
+    }
+    let seed = random_seed();
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, seed)?;
+    Ok(seed)
+}
+
+/// Key a blog and its assets are encrypted with, wrapped with each password which unlocks it.
+pub struct Key {
+    key: [u8; 32],
+    /// Salts and the key encrypted with what is derived from each password with them.
+    slots: Vec<([u8; 16], Vec<u8>)>,
+    /// Whether salts are shared by blogs with the same password.
+    shared: bool,
+}
+
+impl Key {
+    /// Generate a key and salts for the passwords, which shall be at most `MAX_PASSWORDS`. The blog
+    /// is encrypted the same way for the same seed, `context` such as its id, and passwords.
+    pub fn new(passwords: &[String], seed: &[u8], context: &[&[u8]]) -> Self {
+        let mut rng = SeededRng::new(seed, &[&[b"salt".as_slice()], context].concat());
+        Self::with_salts(passwords, seed, context, |_| {
+            let mut salt = [0u8; 16];
+            rng.fill_bytes(&mut salt);
+            salt
+        }, false)
+    }
+
+    /// Like `new`, but the salt of each password only depends on the seed and the password, so that
+    /// what is derived from the password unlocks every blog with it, and readers may remember it.
+    /// Salts are derived as slowly as keys, since they are public as key ids.
+    pub fn shared(passwords: &[String], seed: &[u8], context: &[&[u8]]) -> Self {
+        let seed_salt: [u8; 16] = hmac(seed, &[b"salt"])[..16].try_into().unwrap();
+        Self::with_salts(passwords, seed, context, |password| {
+            derive(password, &seed_salt).0[..16].try_into().unwrap()
+        }, true)
+    }
+
+    fn with_salts(passwords: &[String], seed: &[u8], context: &[&[u8]], mut salt: impl FnMut(&str) -> [u8; 16], shared: bool) -> Self {
+        let derived: Vec<_> = passwords.iter().take(MAX_PASSWORDS).map(|password| {
+            let salt = salt(password);
+            let (password_key, iv) = derive(password, &salt);
+            (salt, password_key, iv)
+        }).collect();
+        // seeded with what is derived from the passwords rather than the passwords, so that the seed
+        // is no shortcut to guess them, while the key still changes with them
+        let keys: Vec<&[u8]> = derived.iter().map(|(_, password_key, _)| password_key.as_slice()).collect();
+        let mut key = [0u8; 32];
+        SeededRng::new(seed, &[context, &keys].concat()).fill_bytes(&mut key);
+        let slots = derived.iter()
+            .map(|(salt, password_key, iv)| (*salt, encrypt_cbc(password_key, iv, &key)))
+            .collect();
+        Self { key, slots, shared }
+    }
+
+    /// Ids of what is derived from each password, which readers may remember it by, as hex of the
+    /// salts. Empty unless salts are shared.
+    pub fn key_ids(&self) -> Vec<String> {
+        if !self.shared {
+            return vec![]
+        }
+        self.slots.iter().map(|(salt, _)| salt.iter().map(|b| format!("{b:02x}")).collect()).collect()
+    }
+
+    /// IV derived from the data, so that the same data is encrypted the same way. Only whether two
+    /// pieces of data encrypted with the same key are identical is revealed.
+    fn synthetic_iv(&self, label: &[u8], data: &[u8]) -> [u8; 16] {
+        hmac(&self.key, &[label, data])[..16].try_into().unwrap()
+    }
+
+    /// Encrypt the blog, prefixed with the number of passwords, a salt and the wrapped key for each
+    /// of them, and the IV.
+    pub fn encrypt(&self, plaintext: &[u8]) -> Vec<u8> {
+        let iv = self.synthetic_iv(b"blog", plaintext);
+        let mut result = vec![self.slots.len() as u8];
+        for (salt, wrapped) in &self.slots {
+            result.extend_from_slice(salt);
+            result.extend_from_slice(wrapped);
+        }
+        result.extend_from_slice(&iv);
+        result.extend(encrypt_cbc(&self.key, &iv, plaintext));
+        result
+    }
+
+    /// Encrypt an asset, prefixed with the IV.
+    pub fn encrypt_asset(&self, data: &[u8]) -> Vec<u8> {
+        let iv = self.synthetic_iv(b"asset", data);
+        let mut result = iv.to_vec();
+        result.extend(encrypt_cbc(&self.key, &iv, data));
+        result
+    }
 
-    let key = &key_iv[..32];
-    let iv = &key_iv[32..];
-    
-    // create buffer for encrypted 
-    let mut buffer = plaintext.as_bytes().to_vec();
-    let pad_len = 16 - (buffer.len() % 16);
-    buffer.resize(buffer.len() + pad_len, 0);
+    /// Replace files in an assets directory with encrypted ones, named after hashes of their paths
+    /// so that names do not reveal anything either. Returns the names by paths relative to `dir`.
+    pub fn encrypt_assets(&self, dir: &Path) -> io::Result<BTreeMap<String, String>> {
+        fn collect(dir: &Path, prefix: &str, files: &mut Vec<(String, Vec<u8>)>) -> io::Result<()> {
+            for entry in fs::read_dir(dir)? {
+                let entry = entry?;
+                let name = entry.file_name().to_string_lossy().into_owned();
+                if entry.file_type()?.is_dir() {
+                    collect(&entry.path(), &format!("{prefix}{name}/"), files)?;
+                } else {
+                    files.push((format!("{prefix}{name}"), fs::read(entry.path())?));
+                }
+            }
+            Ok(())
+        }
+        let mut files = vec![];
+        collect(dir, "", &mut files)?;
+        fs::remove_dir_all(dir)?;
+        fs::create_dir_all(dir)?;
 
-    // Create cipher instance
-    let encryptor = Aes256CbcEnc::new_from_slices(key, iv).unwrap();
+        let mut names = BTreeMap::new();
+        for (path, data) in files {
+            let name = self.asset_name(&path);
+            fs::write(dir.join(&name), self.encrypt_asset(&data))?;
+            names.insert(path, name);
+        }
+        Ok(names)
+    }
 
-    let ciphertext = encryptor.encrypt_padded_mut::<Pkcs7>(&mut buffer, plaintext.len()).unwrap();
-    // Encrypt the data
-    
-    // Combine salt, IV, and ciphertext into a single string (hex encoded)
-    let mut result = Vec::new();
+    /// Encrypt some files of an assets directory next to the others like `encrypt_assets`, leaving
+    /// the files themselves for the caller to remove. Returns the names by paths relative to `dir`.
+    pub fn encrypt_files(&self, dir: &Path, paths: &BTreeSet<String>) -> io::Result<BTreeMap<String, String>> {
+        paths.iter().map(|path| {
+            let name = self.asset_name(path);
+            fs::write(dir.join(&name), self.encrypt_asset(&fs::read(dir.join(path))?))?;
+            Ok((path.clone(), name))
+        }).collect()
+    }
 
-    result.extend_from_slice(&salt);
-    result.extend_from_slice(iv);
-    result.extend_from_slice(ciphertext);
-    
-    result
+    fn asset_name(&self, path: &str) -> String {
+        let hash: String = Sha256::new()
+            .chain_update(self.key)
+            .chain_update(path)
+            .finalize()[..16].iter().map(|b| format!("{b:02x}")).collect();
+        format!("{hash}.bin")
+    }
+}
+
+/// Decrypt what `Key::encrypt` produces with one of its passwords, the same way as `decrypt.js`.
+/// Returns the plaintext, and the key which assets are encrypted with.
+pub fn decrypt(data: &[u8], password: &str) -> Result<(String, [u8; 32]), &'static str> {
+    let slots = *data.first().ok_or("Invalid ciphertext: too short")? as usize;
+    let offset = 1 + slots * 64;
+    if data.len() < offset + 32 {
+        return Err("Invalid ciphertext: too short")
+    }
+    let (iv, ciphertext) = data[offset..].split_at(16);
+    for slot in data[1..offset].chunks(64) {
+        let (password_key, password_iv) = derive(password, slot[..16].try_into().unwrap());
+        // a wrong password almost always fails on padding, otherwise on the content
+        let Some(key) = decrypt_cbc(&password_key, &password_iv, &slot[16..]).ok()
+            .and_then(|key| <[u8; 32]>::try_from(key).ok()) else { continue };
+        if let Some(plaintext) = decrypt_cbc(&key, iv, ciphertext).ok().and_then(|plaintext| String::from_utf8(plaintext).ok()) {
+            return Ok((plaintext, key))
+        }
+    }
+    Err("Decryption failed")
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::testing::TempDir;
     use base64::prelude::*;
-    
+
     #[test]
     fn test_encryption() {
-        let data = "Secret message";
-        let password = "strong password";
-        
-        let encrypted = encrypt_data(data, password);
-        let b64_encoded = BASE64_STANDARD.encode(encrypted);
-        println!("Encrypted data: {}", b64_encoded);
-        
-        // In a real scenario, you would decrypt this in JavaScript
-        // using the same password and parameters
-    }
-}
\ No newline at end of file
+        let passwords = ["reader".to_string(), "family".to_string()];
+        let key = Key::new(&passwords, &random_seed(), &[]);
+        let encrypted = key.encrypt("Secret message".as_bytes());
+        assert_eq!((encrypted[0], encrypted.len()), (2, 1 + 2 * 64 + 16 + 16));
+        // every password unwraps the same key
+        for password in &passwords {
+            assert_eq!(decrypt(&encrypted, password), Ok(("Secret message".to_string(), key.key)));
+        }
+        assert_eq!(decrypt(&encrypted, "stranger"), Err("Decryption failed"));
+        assert_eq!(decrypt(&encrypted[..40], "reader"), Err("Invalid ciphertext: too short"));
+    }
+
+    #[test]
+    fn test_deterministic() {
+        let passwords = ["pw".to_string()];
+        let encrypt = |passwords: &[String], plaintext: &[u8]| {
+            Key::new(passwords, b"seed", &[b"a.md"]).encrypt(plaintext)
+        };
+        let encrypted = encrypt(&passwords, b"Secret message");
+        assert_eq!(encrypted, encrypt(&passwords, b"Secret message"));
+        // the slot is kept when only the content changes
+        let changed = encrypt(&passwords, b"Secret message!");
+        assert_eq!(changed[..65], encrypted[..65]);
+        assert_ne!(changed[65..81], encrypted[65..81]);
+        assert_ne!(encrypt(&["other".to_string()], b"Secret message")[1..], encrypted[1..]);
+        assert_ne!(Key::new(&passwords, b"seed", &[b"b.md"]).encrypt(b"Secret message"), encrypted);
+        // the key changes when a password is revoked, even though the others keep their salts
+        let revoked = Key::new(&passwords, b"seed", &[b"a.md"]);
+        let both = Key::new(&[passwords[0].clone(), "other".to_string()], b"seed", &[b"a.md"]);
+        assert_eq!(both.slots[0].0, revoked.slots[0].0);
+        assert_ne!(both.key, revoked.key);
+
+        // proper PKCS7 padding, a full block for aligned plaintext
+        let key = Key::new(&passwords, b"seed", &[]);
+        assert_eq!(key.encrypt_asset(&[0; 16]).len(), 16 + 32);
+        assert_eq!(key.encrypt_asset(&[0; 15]).len(), 16 + 16);
+        assert_eq!(decrypt(&key.encrypt(b"0123456789abcdef"), "pw").unwrap().0, "0123456789abcdef");
+    }
+
+    #[test]
+    fn test_shared() {
+        let passwords = ["pw".to_string(), "other".to_string()];
+        let a = Key::shared(&passwords, b"seed", &[b"a.md"]);
+        let b = Key::shared(&passwords[..1], b"seed", &[b"b.md"]);
+        assert_eq!(a.key_ids()[0], b.key_ids()[0]);
+        assert_ne!(a.key_ids()[0], a.key_ids()[1]);
+        assert_ne!(a.key, b.key);
+        assert_eq!(decrypt(&b.encrypt(b"B"), "pw").unwrap().0, "B");
+        assert!(Key::new(&passwords, b"seed", &[b"a.md"]).key_ids().is_empty());
+    }
+
+    /// Produced by WebCrypto from password "known answer", salt 00..0f, key 40..5f and IV of 07s.
+    #[test]
+    fn test_known_answer() {
+        let data = BASE64_STANDARD.decode("AQABAgMEBQYHCAkKCwwNDg/nRKGSBedRZrFfIvdUbJh8fJ9j9CXOuYM3qrrFA3lMdVQGFgfMHkCyPvCaSFXuaO0HBwcHBwcHBwcHBwcHBwcHwinNvMba8mPE6+pDl5aujxjItWPaY2k9ihhkNCETONM=").unwrap();
+        let (plaintext, key) = decrypt(&data, "known answer").unwrap();
+        assert_eq!(plaintext, "<p>Known answer</p>");
+        assert_eq!(key, std::array::from_fn(|i| 0x40 + i as u8));
+        assert!(decrypt(&data, "wrong answer").is_err());
+    }
+
+    #[test]
+    fn test_encrypt_assets() {
+        let dir = TempDir::new("crypto");
+        fs::create_dir_all(dir.join("photos")).unwrap();
+        fs::write(dir.join("photos").join("home.jpg"), "image data").unwrap();
+        fs::write(dir.join("notes.txt"), "notes").unwrap();
+
+        let key = Key::new(&["pw".to_string()], &random_seed(), &[]);
+        let names = key.encrypt_assets(&dir).unwrap();
+        assert_eq!(names.keys().collect::<Vec<_>>(), ["notes.txt", "photos/home.jpg"]);
+        let mut files: Vec<_> = fs::read_dir(&dir).unwrap().map(|entry| entry.unwrap().file_name().into_string().unwrap()).collect();
+        files.sort();
+        let mut expected: Vec<_> = names.values().cloned().collect();
+        expected.sort();
+        assert_eq!(files, expected);
+
+        // assets are decrypted with the key unwrapped along with the blog
+        let (_, unwrapped) = decrypt(&key.encrypt(b""), "pw").unwrap();
+        let data = fs::read(dir.join(&names["photos/home.jpg"])).unwrap();
+        let (iv, ciphertext) = data.split_at(16);
+        assert_eq!(decrypt_cbc(&unwrapped, iv, ciphertext).unwrap(), b"image data");
+
+        // assets of a secret section are encrypted next to the others with the key of the section
+        fs::write(dir.join("secret.png"), "secret image").unwrap();
+        let section_key = Key::new(&["section".to_string()], &random_seed(), &[]);
+        let section_names = section_key.encrypt_files(&dir, &BTreeSet::from(["secret.png".to_string()])).unwrap();
+        let (_, unwrapped) = decrypt(&section_key.encrypt(b""), "section").unwrap();
+        let data = fs::read(dir.join(&section_names["secret.png"])).unwrap();
+        let (iv, ciphertext) = data.split_at(16);
+        assert_eq!(decrypt_cbc(&unwrapped, iv, ciphertext).unwrap(), b"secret image");
+        assert!(dir.join(&names["photos/home.jpg"]).is_file());
+    }
+}
@@ -0,0 +1,269 @@
+use std::io::{Cursor, Write};
+use std::process::{Command, Stdio};
+use std::{fs, path::Path};
+
+use quick_xml::events::{BytesEnd, BytesStart, BytesText, Event};
+use quick_xml::Writer;
+use sha2::{Digest, Sha256};
+
+/// Changes whenever diagrams are rendered differently, so that cached ones are not reused.
+const PIPELINE_VERSION: &str = "2";
+
+/// Renders diagrams in code blocks of a language to SVG, so that any tool can draw them.
+pub trait DiagramRenderer {
+    /// Language of code blocks it renders, such as `dot`.
+    fn language(&self) -> &str;
+    /// SVG of the diagram, or what is wrong with it. Ids in the SVG shall derive from `id`, which is
+    /// unique to the diagram, so that those of diagrams in the same page do not clash.
+    fn render(&self, source: &str, id: &str) -> Result<String, String>;
+}
+
+/// Renderer running a program which reads the source from stdin and writes SVG to stdout.
+pub struct CommandRenderer {
+    language: String,
+    program: String,
+    args: Vec<String>,
+    /// Option the id of the diagram is passed with, ids in the output are prefixed with it otherwise.
+    id_arg: Option<String>,
+}
+
+impl CommandRenderer {
+    pub fn new(language: &str, program: &str, args: &[&str]) -> Self {
+        Self {
+            language: language.to_string(),
+            program: program.to_string(),
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+            id_arg: None,
+        }
+    }
+
+    /// Graphviz for ```` ```dot ```` blocks.
+    pub fn graphviz() -> Self {
+        Self::new("dot", "dot", &["-Tsvg"])
+    }
+
+    /// Mermaid CLI for ```` ```mermaid ```` blocks, in its neutral theme which the page recolours.
+    pub fn mermaid() -> Self {
+        let args = ["--input", "-", "--output", "-", "--outputFormat", "svg", "--quiet", "--theme", "neutral", "--backgroundColor", "transparent"];
+        Self { id_arg: Some("--svgId".to_string()), ..Self::new("mermaid", "mmdc", &args) }
+    }
+}
+
+impl DiagramRenderer for CommandRenderer {
+    fn language(&self) -> &str {
+        &self.language
+    }
+
+    fn render(&self, source: &str, id: &str) -> Result<String, String> {
+        let mut child = Command::new(&self.program)
+            .args(&self.args)
+            .args(self.id_arg.iter().flat_map(|arg| [arg.as_str(), id]))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("cannot run {}: {e}", self.program))?;
+        child.stdin.take().unwrap().write_all(source.as_bytes()).map_err(|e| e.to_string())?;
+        let output = child.wait_with_output().map_err(|e| e.to_string())?;
+        if !output.status.success() {
+            return Err(String::from_utf8_lossy(&output.stderr).trim().to_string())
+        }
+        let svg = String::from_utf8(output.stdout).map_err(|e| e.to_string())?;
+        match self.id_arg {
+            Some(_) => Ok(svg),
+            None => prefix_ids(&svg, id),
+        }
+    }
+}
+
+/// Prefix ids in an SVG along with references to them in `href` and `url(#…)`.
+fn prefix_ids(svg: &str, prefix: &str) -> Result<String, String> {
+    let mut reader = quick_xml::reader::Reader::from_str(svg);
+    let mut writer = Writer::new(Cursor::new(Vec::new()));
+    let rewrite = |e: &BytesStart| {
+        let mut start = e.to_owned();
+        start.clear_attributes();
+        for attr in e.attributes().flatten() {
+            let Ok(value) = attr.unescape_value() else {
+                start.push_attribute(attr);
+                continue
+            };
+            let prefixed = match attr.key.0 {
+                b"id" => format!("{prefix}-{value}"),
+                b"href" | b"xlink:href" if value.starts_with('#') => format!("#{prefix}-{}", &value[1..]),
+                _ => value.replace("url(#", &format!("url(#{prefix}-")),
+            };
+            match prefixed == value {
+                true => start.push_attribute(attr),
+                false => start.push_attribute((attr.key.0, prefixed.as_bytes())),
+            }
+        }
+        start.into_owned()
+    };
+    loop {
+        match reader.read_event() {
+            Ok(Event::Start(e)) => writer.write_event(Event::Start(rewrite(&e))).unwrap(),
+            Ok(Event::Empty(e)) => writer.write_event(Event::Empty(rewrite(&e))).unwrap(),
+            Ok(Event::Eof) => break,
+            Ok(e) => writer.write_event(e).unwrap(),
+            Err(e) => return Err(e.to_string()),
+        }
+    }
+    Ok(String::from_utf8(writer.into_inner().into_inner()).unwrap())
+}
+
+/// The `<svg>` element of a rendered diagram without the XML declaration and doctype before it,
+/// if it is well-formed so that it can be embedded.
+fn inline_svg(svg: &str) -> Option<&str> {
+    let svg = svg[svg.find("<svg")?..].trim_end();
+    let mut reader = quick_xml::reader::Reader::from_str(svg);
+    let mut depth = 0usize;
+    loop {
+        match reader.read_event() {
+            Ok(Event::Start(_)) => depth += 1,
+            Ok(Event::End(_)) if depth == 0 => return None,
+            Ok(Event::End(_)) => depth -= 1,
+            Ok(Event::Eof) => return (depth == 0).then_some(svg),
+            Ok(_) => {},
+            Err(_) => return None,
+        }
+    }
+}
+
+/// Render a diagram, reusing the SVG kept in `cache` by the hash of its source.
+fn render_cached(renderer: &dyn DiagramRenderer, source: &str, cache: &Path) -> Result<String, String> {
+    let hash: String = Sha256::new()
+        .chain_update(PIPELINE_VERSION)
+        .chain_update(renderer.language())
+        .chain_update([0])
+        .chain_update(source)
+        .finalize().iter().map(|b| format!("{b:02x}")).collect();
+    let cached_path = cache.join(format!("{hash}.svg"));
+    if let Ok(svg) = fs::read_to_string(&cached_path) {
+        return Ok(svg)
+    }
+    // the same diagram has the same ids, which is harmless since their elements are the same
+    let svg = renderer.render(source, &format!("diagram-{}", &hash[..12]))?;
+    let svg = inline_svg(&svg).ok_or("the output is not a well-formed SVG")?.to_string();
+    // failing to cache only makes the next run slower
+    let _ = fs::create_dir_all(cache).and_then(|_| fs::write(&cached_path, &svg));
+    Ok(svg)
+}
+
+/// Replace code blocks in languages of the renderers by inline SVG of the diagrams, kept in `cache`.
+/// Blocks which cannot be rendered are left as they are, with diagnostics of them.
+pub fn render_diagrams(html: &str, renderers: &[Box<dyn DiagramRenderer>], cache: &Path) -> (String, Vec<String>) {
+    let mut reader = quick_xml::reader::Reader::from_str(html);
+    let mut writer = Writer::new(Cursor::new(Vec::new()));
+    let mut diagnostics = vec![];
+    // inner events of the preformatted block being read
+    let mut block: Option<Vec<Event<'static>>> = None;
+    loop {
+        match reader.read_event() {
+            Ok(Event::Start(e)) if block.is_none() && e.name().0 == b"pre" => block = Some(vec![Event::Start(e.into_owned())]),
+            Ok(Event::End(e)) if block.is_some() && e.name().0 == b"pre" => {
+                let mut events = block.take().unwrap();
+                events.push(Event::End(e.into_owned()));
+                let renderer = match &events[..] {
+                    [Event::Start(_), Event::Start(code), Event::Text(_), Event::End(_), Event::End(_)] if code.name().0 == b"code" => {
+                        code.try_get_attribute("class").ok().flatten()
+                            .and_then(|class| class.unescape_value().ok().map(|class| class.into_owned()))
+                            .and_then(|class| renderers.iter().find(|renderer| class == format!("language-{}", renderer.language())))
+                    }
+                    _ => None,
+                };
+                let rendered = renderer.and_then(|renderer| {
+                    let Event::Text(source) = &events[2] else { unreachable!() };
+                    let source = source.unescape().unwrap_or_default();
+                    render_cached(renderer.as_ref(), &source, cache)
+                        .map_err(|e| diagnostics.push(format!("{} diagram cannot be rendered: {e}", renderer.language())))
+                        .ok()
+                        .map(|svg| (renderer.language(), svg))
+                });
+                match rendered {
+                    Some((language, svg)) => {
+                        let mut figure = BytesStart::new("figure");
+                        figure.push_attribute(("class", format!("diagram diagram-{language}").as_str()));
+                        writer.write_event(Event::Start(figure)).unwrap();
+                        writer.write_event(Event::Text(BytesText::from_escaped(svg))).unwrap();
+                        writer.write_event(Event::End(BytesEnd::new("figure"))).unwrap();
+                    }
+                    None => {
+                        for event in events {
+                            writer.write_event(event).unwrap();
+                        }
+                    }
+                }
+            }
+            Ok(Event::Eof) => break,
+            Ok(e) => match &mut block {
+                Some(events) => events.push(e.into_owned()),
+                None => writer.write_event(e).unwrap(),
+            },
+            Err(_) => panic!("Fail to parse html"),
+        }
+    }
+    (String::from_utf8(writer.into_inner().into_inner()).unwrap(), diagnostics)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+    use crate::render;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    /// Draws a box labelled with the source, counting how many times it is asked to.
+    struct BoxRenderer(Rc<Cell<usize>>);
+
+    impl DiagramRenderer for BoxRenderer {
+        fn language(&self) -> &str {
+            "box"
+        }
+        fn render(&self, source: &str, id: &str) -> Result<String, String> {
+            self.0.set(self.0.get() + 1);
+            match source.trim() {
+                source if source.ends_with("->") => Err("syntax error in line 1".to_string()),
+                "broken" => Ok("<svg><g></svg>".to_string()),
+                label => Ok(format!("<?xml version=\"1.0\"?>\n<!DOCTYPE svg>\n<svg id=\"{id}\" viewBox=\"0 0 10 10\"><text>{label}</text></svg>\n")),
+            }
+        }
+    }
+
+    #[test]
+    fn test_render_diagrams() {
+        let cache = TempDir::new("diagrams");
+        let html = render::render("```box\na -> b\n```\n\n```box\na ->\n```\n\n```box\nbroken\n```\n\n```rust\nfn main() {}\n```\n\n```box\nb -> a\n```\n").html;
+        let calls = Rc::new(Cell::new(0));
+        let renderers: Vec<Box<dyn DiagramRenderer>> = vec![Box::new(BoxRenderer(calls.clone()))];
+
+        let (rendered, diagnostics) = render_diagrams(&html, &renderers, &cache);
+        assert!(rendered.starts_with(r#"<figure class="diagram diagram-box"><svg id="diagram-"#));
+        assert!(rendered.contains(r#"" viewBox="0 0 10 10"><text>a -> b</text></svg></figure>"#));
+        // diagrams in the same page have their own ids
+        let ids: Vec<_> = rendered.match_indices(r#"<svg id=""#).map(|(i, _)| &rendered[i..i + 30]).collect();
+        assert!(ids.len() == 2 && ids[0] != ids[1]);
+        assert!(rendered.contains(r#"<pre><code class="language-box">a -&gt;"#));
+        assert!(rendered.contains(r#"<pre><code class="language-box">broken"#));
+        assert!(rendered.contains(r#"<pre><code class="language-rust">fn main() {}"#));
+        assert_eq!(diagnostics, [
+            "box diagram cannot be rendered: syntax error in line 1",
+            "box diagram cannot be rendered: the output is not a well-formed SVG",
+        ]);
+        assert_eq!(calls.get(), 4);
+
+        // only diagrams rendered fine are cached
+        assert_eq!(render_diagrams(&html, &renderers, &cache).0, rendered);
+        assert_eq!(calls.get(), 6);
+    }
+
+    #[test]
+    fn test_prefix_ids() {
+        let svg = r##"<svg><defs><linearGradient id="l_1"/></defs><g id="graph0"><a xlink:href="#node1" href="https://example.com"><path fill="url(#l_1)" stroke="black"/></a></g></svg>"##;
+        assert_eq!(prefix_ids(svg, "diagram-1").unwrap(), concat!(
+            r##"<svg><defs><linearGradient id="diagram-1-l_1"/></defs><g id="diagram-1-graph0">"##,
+            r##"<a xlink:href="#diagram-1-node1" href="https://example.com"><path fill="url(#diagram-1-l_1)" stroke="black"/></a></g></svg>"##,
+        ));
+    }
+}
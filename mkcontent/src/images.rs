@@ -0,0 +1,277 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::io::Cursor;
+use std::{fs, io, path::Path};
+
+use image::codecs::avif::AvifEncoder;
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageDecoder, ImageFormat, ImageReader};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Widths of resized variants, only those narrower than the image are generated.
+const WIDTHS: [u32; 3] = [480, 960, 1440];
+
+/// Changes whenever variants are generated differently, so that cached ones are not reused.
+const PIPELINE_VERSION: &str = "1";
+
+/// Directory next to images where their variants are, so that they never overwrite files of the author.
+pub const VARIANTS_DIR: &str = ".variants";
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    Jpeg,
+    Png,
+    Webp,
+    Avif,
+}
+
+impl Format {
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        match extension.to_ascii_lowercase().as_str() {
+            "jpg" | "jpeg" => Some(Format::Jpeg),
+            "png" => Some(Format::Png),
+            "webp" => Some(Format::Webp),
+            _ => None,
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Jpeg => "jpg",
+            Format::Png => "png",
+            Format::Webp => "webp",
+            Format::Avif => "avif",
+        }
+    }
+
+    pub fn mime(self) -> &'static str {
+        match self {
+            Format::Jpeg => "image/jpeg",
+            Format::Png => "image/png",
+            Format::Webp => "image/webp",
+            Format::Avif => "image/avif",
+        }
+    }
+
+    fn encode(self, image: &DynamicImage) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut bytes = vec![];
+        match self {
+            Format::Jpeg => image.to_rgb8().write_with_encoder(JpegEncoder::new_with_quality(&mut bytes, 80))?,
+            Format::Png => image.write_to(&mut Cursor::new(&mut bytes), ImageFormat::Png)?,
+            Format::Webp => {
+                let image = if image.color().has_alpha() {
+                    DynamicImage::ImageRgba8(image.to_rgba8())
+                } else {
+                    DynamicImage::ImageRgb8(image.to_rgb8())
+                };
+                bytes = webp::Encoder::from_image(&image)?.encode(80.0).to_vec();
+            },
+            Format::Avif => image.write_with_encoder(AvifEncoder::new_with_speed_quality(&mut bytes, 8, 70))?,
+        }
+        Ok(bytes)
+    }
+}
+
+/// Variants generated for a raster image, which is displayed at `width` by `height`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ResponsiveImage {
+    pub width: u32,
+    pub height: u32,
+    /// Format of the image itself, which browsers fall back to.
+    pub format: Format,
+    /// Widths of variants of each format. The image itself is not included.
+    pub variants: Vec<(Format, Vec<u32>)>,
+}
+
+impl ResponsiveImage {
+    /// Path of a variant in `VARIANTS_DIR`, from the path of the image.
+    pub fn variant_name(path: &str, width: u32, format: Format) -> String {
+        let (dir, name) = path.rsplit_once('/').map_or(("", path), |(dir, name)| (dir, name));
+        let stem = name.rsplit_once('.').map_or(name, |(stem, _)| stem);
+        let dir = if dir.is_empty() { String::new() } else { format!("{dir}/") };
+        format!("{dir}{VARIANTS_DIR}/{stem}-{width}.{}", format.extension())
+    }
+}
+
+/// File name of a variant in the cache.
+fn cached_name(width: u32, format: Format) -> String {
+    format!("image-{width}.{}", format.extension())
+}
+
+/// Encode images of each width into `dir` as cached variants, returning their widths.
+fn write_variants(images: &[(u32, DynamicImage)], format: Format, dir: &Path) -> Result<Vec<u32>, Box<dyn Error>> {
+    images.iter().map(|(width, image)| {
+        fs::write(dir.join(cached_name(*width, format)), format.encode(image)?)?;
+        Ok(*width)
+    }).collect()
+}
+
+/// Generate variants of an image into `dir`.
+fn generate(bytes: &[u8], format: Format, avif: bool, dir: &Path) -> Result<ResponsiveImage, Box<dyn Error>> {
+    let mut decoder = ImageReader::new(Cursor::new(bytes)).with_guessed_format()?.into_decoder()?;
+    let orientation = decoder.orientation()?;
+    let mut image = DynamicImage::from_decoder(decoder)?;
+    image.apply_orientation(orientation);
+    let (width, height) = (image.width(), image.height());
+
+    let mut resized: Vec<_> = WIDTHS.into_iter()
+        .filter(|&w| w < width)
+        .map(|w| (w, image.resize(w, u32::MAX, FilterType::Lanczos3)))
+        .collect();
+    // the fallback format is resized only, modern formats are also generated at full width
+    let mut variants = vec![(format, write_variants(&resized, format, dir)?)];
+    resized.push((width, image));
+    for modern in [Format::Avif, Format::Webp] {
+        if modern != format && (modern != Format::Avif || avif) {
+            variants.push((modern, write_variants(&resized, modern, dir)?));
+        }
+    }
+    Ok(ResponsiveImage { width, height, format, variants })
+}
+
+/// Images of an assets directory with their variants.
+#[derive(Debug, Default, PartialEq)]
+pub struct Assets {
+    /// Images by paths relative to the assets directory.
+    pub images: BTreeMap<String, ResponsiveImage>,
+    /// Entries of the cache which variants are copied from.
+    pub cached: BTreeSet<String>,
+}
+
+/// Generate variants of raster images in `src` into `dst`. Images not copied to `dst` are skipped.
+/// Generated variants are kept in `cache` by the hash of the image, and reused from there.
+/// AVIF variants are only generated with `avif`, since encoding them takes far longer.
+pub fn process_assets(src: &Path, dst: &Path, cache: &Path, avif: bool) -> Result<Assets, Box<dyn Error>> {
+    let mut assets = Assets::default();
+    process_dir(src, dst, cache, avif, "", &mut assets)?;
+    Ok(assets)
+}
+
+/// Remove entries of the cache other than `used`, such as those of images no longer in any blog.
+pub fn prune_cache(cache: &Path, used: &BTreeSet<String>) -> io::Result<()> {
+    for entry in fs::read_dir(cache)? {
+        let entry = entry?;
+        if !used.contains(&*entry.file_name().to_string_lossy()) {
+            fs::remove_dir_all(entry.path())?;
+        }
+    }
+    Ok(())
+}
+
+fn process_dir(
+    src: &Path,
+    dst: &Path,
+    cache: &Path,
+    avif: bool,
+    prefix: &str,
+    assets: &mut Assets,
+) -> Result<(), Box<dyn Error>> {
+    for entry in fs::read_dir(src)? {
+        let entry = entry?;
+        let Ok(name) = entry.file_name().into_string() else { continue };
+        if name == VARIANTS_DIR {
+            return Err(format!("{prefix}{name} is kept for resized images").into())
+        }
+        if entry.file_type()?.is_dir() {
+            process_dir(&entry.path(), &dst.join(&name), cache, avif, &format!("{prefix}{name}/"), assets)?;
+            continue
+        }
+        let Some(format) = name.rsplit_once('.').and_then(|(_, extension)| Format::from_extension(extension)) else {
+            continue
+        };
+        // left out of `dst`, such as when its metadata cannot be removed
+        if !dst.join(&name).is_file() {
+            continue
+        }
+        let bytes = fs::read(entry.path())?;
+        let hash: String = Sha256::new()
+            .chain_update(PIPELINE_VERSION)
+            .chain_update([avif as u8])
+            .chain_update(&bytes)
+            .finalize().iter().map(|b| format!("{b:02x}")).collect();
+        let cached_path = cache.join(&hash);
+        let meta_path = cached_path.join("image.json");
+
+        let image = match fs::read_to_string(&meta_path).ok().and_then(|s| serde_json::from_str::<ResponsiveImage>(&s).ok()) {
+            Some(image) => image,
+            None => {
+                fs::create_dir_all(&cached_path)?;
+                let image = generate(&bytes, format, avif, &cached_path).map_err(|e| format!("cannot process {prefix}{name}: {e}"))?;
+                // written last, so that an interrupted run is not taken as cached
+                fs::write(&meta_path, serde_json::to_string(&image)?)?;
+                image
+            }
+        };
+        if image.variants.iter().any(|(_, widths)| !widths.is_empty()) {
+            fs::create_dir_all(dst.join(VARIANTS_DIR))?;
+        }
+        for (format, widths) in &image.variants {
+            for &width in widths {
+                fs::copy(cached_path.join(cached_name(width, *format)), dst.join(ResponsiveImage::variant_name(&name, width, *format)))?;
+            }
+        }
+        assets.images.insert(format!("{prefix}{name}"), image);
+        assets.cached.insert(hash);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+    use crate::render;
+
+    #[test]
+    fn test_process_assets() {
+        let dir = TempDir::new("images");
+        let (src, dst, cache) = (dir.join("src"), dir.join("dst"), dir.join("cache"));
+        fs::create_dir_all(src.join("photos")).unwrap();
+        fs::create_dir_all(dst.join("photos")).unwrap();
+        let photo = image::RgbImage::from_fn(600, 400, |x, y| image::Rgb([(x % 256) as u8, (y % 256) as u8, 128]));
+        photo.save(src.join("photos").join("a b.jpg")).unwrap();
+        fs::write(src.join("photos").join("left out.png"), "not copied").unwrap();
+        fs::write(src.join("notes.txt"), "not an image").unwrap();
+        fs::copy(src.join("photos").join("a b.jpg"), dst.join("photos").join("a b.jpg")).unwrap();
+
+        // a file of the author named like a variant
+        fs::write(dst.join("photos").join("a b-480.jpg"), "mine").unwrap();
+
+        let assets = process_assets(&src, &dst, &cache, false).unwrap();
+        let image = &assets.images["photos/a b.jpg"];
+        assert_eq!((image.width, image.height, image.format), (600, 400, Format::Jpeg));
+        assert_eq!(image.variants, [
+            (Format::Jpeg, vec![480]),
+            (Format::Webp, vec![480, 600]),
+        ]);
+        assert!(dst.join("photos").join(".variants").join("a b-480.jpg").is_file());
+        assert!(dst.join("photos").join(".variants").join("a b-600.webp").is_file());
+        assert_eq!(fs::read(dst.join("photos").join("a b-480.jpg")).unwrap(), b"mine");
+        assert_eq!((assets.images.len(), assets.cached.len()), (1, 1));
+
+        // variants are copied from the cache afterwards
+        fs::remove_dir_all(&dst).unwrap();
+        fs::create_dir_all(dst.join("photos")).unwrap();
+        fs::copy(src.join("photos").join("a b.jpg"), dst.join("photos").join("a b.jpg")).unwrap();
+        assert_eq!(process_assets(&src, &dst, &cache, false).unwrap(), assets);
+        assert!(dst.join("photos").join(".variants").join("a b-480.webp").is_file());
+
+        // entries of images no longer used are removed
+        fs::create_dir_all(cache.join("unused")).unwrap();
+        prune_cache(&cache, &assets.cached).unwrap();
+        assert_eq!(fs::read_dir(&cache).unwrap().count(), 1);
+        assert_eq!(process_assets(&src, &dst, &cache, false).unwrap(), assets);
+
+        // the directory of variants is not taken from the author
+        fs::create_dir_all(src.join(".variants")).unwrap();
+        assert!(process_assets(&src, &dst, &cache, false).is_err());
+
+        let html = render::responsive_images(r#"<p><img src="post.assets/photos/a%20b.jpg" alt="A"/></p>"#, "post.assets", &assets.images);
+        assert!(html.starts_with(r#"<p><picture><source type="image/webp" srcset="post.assets/photos/.variants/a%20b-480.webp 480w, "#));
+        assert!(html.contains(r#"srcset="post.assets/photos/.variants/a%20b-480.jpg 480w, post.assets/photos/a%20b.jpg 600w""#));
+        assert!(html.ends_with(r#"width="600" height="400"/></picture></p>"#));
+    }
+}
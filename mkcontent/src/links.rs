@@ -0,0 +1,269 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Display;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::{fs, path::Path, thread};
+
+use serde::{Deserialize, Serialize};
+
+use crate::render;
+
+/// Issues requests for the link checker, so checks can run against anything speaking HTTP.
+pub trait HttpClient: Sync {
+    /// Status code the url responds with after following redirects.
+    fn status(&self, url: &str, method: Method) -> Result<u16, String>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Method {
+    Head,
+    Get,
+}
+
+/// Client backed by ureq, which gives up on a request after the timeout.
+pub struct UreqClient(ureq::Agent);
+
+impl UreqClient {
+    pub fn new(timeout: Duration) -> Self {
+        let config = ureq::Agent::config_builder()
+            .timeout_global(Some(timeout))
+            .http_status_as_error(false)
+            .user_agent(concat!("mkcontent/", env!("CARGO_PKG_VERSION")))
+            .build();
+        Self(config.into())
+    }
+}
+
+impl HttpClient for UreqClient {
+    fn status(&self, url: &str, method: Method) -> Result<u16, String> {
+        let response = match method {
+            Method::Head => self.0.head(url).call(),
+            Method::Get => self.0.get(url).call(),
+        };
+        response.map(|response| response.status().as_u16()).map_err(|e| e.to_string())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase", tag = "result", content = "detail")]
+pub enum LinkStatus {
+    Ok(u16),
+    Broken(u16),
+    Error(String),
+}
+
+impl Display for LinkStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LinkStatus::Ok(status) => write!(f, "ok ({status})"),
+            LinkStatus::Broken(status) => write!(f, "broken ({status})"),
+            LinkStatus::Error(e) => write!(f, "error ({e})"),
+        }
+    }
+}
+
+impl LinkStatus {
+    pub fn is_ok(&self) -> bool {
+        matches!(self, LinkStatus::Ok(_))
+    }
+}
+
+/// Check an url with HEAD, falling back to GET for servers which do not support it.
+pub fn check_url(client: &impl HttpClient, url: &str) -> LinkStatus {
+    let status = match client.status(url, Method::Head) {
+        Ok(405 | 501) | Err(_) => client.status(url, Method::Get),
+        status => status,
+    };
+    match status {
+        Ok(status) if status < 400 => LinkStatus::Ok(status),
+        Ok(status) => LinkStatus::Broken(status),
+        Err(e) => LinkStatus::Error(e),
+    }
+}
+
+/// Previous result of a link which was fine, with the unix time it was checked at.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CachedLink {
+    pub status: LinkStatus,
+    pub checked: u64,
+}
+
+/// Results of previous runs. Only working links are kept, broken ones are always checked again.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct LinkCache(pub BTreeMap<String, CachedLink>);
+
+impl LinkCache {
+    /// Load the cache, which is empty if the file does not exist or cannot be read.
+    pub fn load(path: impl AsRef<Path>) -> Self {
+        fs::read_to_string(path).ok()
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .unwrap_or_default()
+    }
+
+    fn fresh(&self, url: &str, now: u64, max_age: Duration) -> Option<&LinkStatus> {
+        self.0.get(url)
+            .filter(|cached| now.saturating_sub(cached.checked) < max_age.as_secs())
+            .map(|cached| &cached.status)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct LinkResult {
+    pub url: String,
+    pub status: LinkStatus,
+    /// Whether the result is taken from the cache.
+    pub cached: bool,
+    /// Blogs linking to the url.
+    pub blogs: BTreeSet<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LinkReport {
+    pub checked: usize,
+    pub broken: usize,
+    pub links: Vec<LinkResult>,
+}
+
+/// External urls of links and images in each blog, by url.
+pub fn external_links(blogs_path: impl AsRef<Path>) -> BTreeMap<String, BTreeSet<String>> {
+    let mut links: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+    let entries = fs::read_dir(blogs_path).into_iter().flatten().flatten();
+    for entry in entries {
+        let Ok(id) = entry.file_name().into_string() else { continue };
+        if !id.ends_with(".md") {
+            continue
+        }
+        let Ok(content) = fs::read_to_string(entry.path()) else { continue };
+        let rendered = render::render(&content);
+        for url in rendered.links.into_iter().chain(rendered.images) {
+            if url.starts_with("http://") || url.starts_with("https://") {
+                // fragments are not sent to the server
+                let url = url.split('#').next().unwrap_or_default().to_string();
+                links.entry(url).or_default().insert(id.clone());
+            }
+        }
+    }
+    links
+}
+
+/// Check links concurrently, reusing cached results younger than `max_age` and updating the cache.
+pub fn check_links(
+    client: &impl HttpClient,
+    links: BTreeMap<String, BTreeSet<String>>,
+    cache: &mut LinkCache,
+    max_age: Duration,
+    concurrency: usize,
+) -> LinkReport {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+    let (cached, unchecked): (Vec<_>, Vec<_>) = links.into_iter()
+        .partition(|(url, _)| cache.fresh(url, now, max_age).is_some());
+
+    let mut results: Vec<_> = cached.into_iter().map(|(url, blogs)| LinkResult {
+        status: cache.fresh(&url, now, max_age).unwrap().clone(),
+        url,
+        cached: true,
+        blogs,
+    }).collect();
+    let checked = unchecked.len();
+
+    let next = AtomicUsize::new(0);
+    let checked_results = Mutex::new(vec![]);
+    thread::scope(|scope| {
+        for _ in 0..concurrency.clamp(1, checked.max(1)) {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let Some((url, _)) = unchecked.get(index) else { break };
+                let status = check_url(client, url);
+                checked_results.lock().unwrap().push((index, status));
+            });
+        }
+    });
+
+    let mut statuses: Vec<Option<LinkStatus>> = vec![None; checked];
+    for (index, status) in checked_results.into_inner().unwrap() {
+        statuses[index] = Some(status);
+    }
+    for ((url, blogs), status) in unchecked.into_iter().zip(statuses) {
+        let status = status.unwrap();
+        if status.is_ok() {
+            cache.0.insert(url.clone(), CachedLink { status: status.clone(), checked: now });
+        } else {
+            cache.0.remove(&url);
+        }
+        results.push(LinkResult { url, status, cached: false, blogs });
+    }
+    results.sort_by(|a, b| a.url.cmp(&b.url));
+
+    LinkReport {
+        checked,
+        broken: results.iter().filter(|result| !result.status.is_ok()).count(),
+        links: results,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+
+    /// Serve `/ok`, `/no-head` (GET only) and `/slow` forever, everything else is 404.
+    fn serve() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                thread::spawn(move || {
+                    let mut reader = BufReader::new(&stream);
+                    let mut request_line = String::new();
+                    reader.read_line(&mut request_line).unwrap();
+                    let mut line = String::new();
+                    while reader.read_line(&mut line).is_ok_and(|n| n > 2) {
+                        line.clear();
+                    }
+                    let mut parts = request_line.split_whitespace();
+                    let status = match (parts.next().unwrap_or_default(), parts.next().unwrap_or_default()) {
+                        (_, "/ok") => "200 OK",
+                        ("HEAD", "/no-head") => "405 Method Not Allowed",
+                        ("GET", "/no-head") => "200 OK",
+                        (_, "/slow") => {
+                            thread::sleep(Duration::from_secs(2));
+                            "200 OK"
+                        },
+                        _ => "404 Not Found",
+                    };
+                    let _ = (&stream).write_all(format!("HTTP/1.1 {status}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n").as_bytes());
+                });
+            }
+        });
+        format!("http://{address}")
+    }
+
+    #[test]
+    fn test_check_links() {
+        let server = serve();
+        let client = UreqClient::new(Duration::from_millis(500));
+        let links: BTreeMap<_, _> = ["/ok", "/no-head", "/missing", "/slow"].into_iter()
+            .map(|path| (format!("{server}{path}"), BTreeSet::from(["a.md".to_string()])))
+            .collect();
+        let max_age = Duration::from_secs(60);
+
+        let mut cache = LinkCache::default();
+        let report = check_links(&client, links.clone(), &mut cache, max_age, 4);
+        let statuses: BTreeMap<_, _> = report.links.iter()
+            .map(|result| (result.url.strip_prefix(&server).unwrap(), &result.status))
+            .collect();
+        assert_eq!(statuses["/ok"], &LinkStatus::Ok(200));
+        assert_eq!(statuses["/no-head"], &LinkStatus::Ok(200));
+        assert_eq!(statuses["/missing"], &LinkStatus::Broken(404));
+        assert!(matches!(statuses["/slow"], LinkStatus::Error(_)));
+        assert_eq!((report.checked, report.broken), (4, 2));
+
+        // only working links are cached
+        let report = check_links(&client, links, &mut cache, max_age, 4);
+        assert_eq!((report.checked, report.broken), (2, 2));
+        assert!(report.links.iter().all(|result| result.cached == result.status.is_ok()));
+    }
+}
@@ -6,21 +6,46 @@ pub(crate) mod crypto;
 
 pub(crate) mod render;
 
+pub(crate) mod migration;
 
-use blog::{manifest::BlogManifest, Blog};
-use clap::Parser;
+pub(crate) mod types;
+
+pub(crate) mod schema;
+
+pub(crate) mod check;
+
+pub(crate) mod links;
+
+pub(crate) mod images;
+
+pub(crate) mod diagrams;
+
+pub(crate) mod blocks;
+
+pub(crate) mod metadata;
+
+#[cfg(test)]
+pub(crate) mod testing;
+
+
+use blog::{keyring::Keyring, manifest::BlogManifest, tag::TagRegistry, Blog};
+use clap::{Parser, Subcommand};
 use render::{save_html, save_html_secret};
 use site::manifest::SiteManifest;
-use std::collections::HashSet;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
 use std::process;
+use std::time::Duration;
 use std::{io, fs, path::Path};
 use std::io::{stdout, Write};
 use base64::prelude::*;
 
 /// Load blog content to static
 #[derive(Parser)]
-#[command(version, about, long_about = None)]
+#[command(version, about, long_about = None, args_conflicts_with_subcommands = true)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     #[arg(default_value_t = String::from("../content"))]
     input: String,
 
@@ -41,6 +66,80 @@ struct Args {
     /// Do not encrypt secret blogs.
     #[arg(long)]
     no_encrypt: bool,
+
+    /// Pretty-print manifests.
+    #[arg(long)]
+    pretty: bool,
+
+    /// Also write paginated listings and per-blog metadata besides the blog manifest.
+    #[arg(long)]
+    sharded: bool,
+
+    /// Number of blogs in each page of sharded listings.
+    #[arg(long, default_value_t = 20)]
+    page_size: usize,
+
+    /// Also generate AVIF variants of images, which takes far longer.
+    #[arg(long)]
+    avif: bool,
+
+    /// Also render ```mermaid blocks to SVG with mmdc, ```dot blocks are always rendered with Graphviz.
+    #[arg(long)]
+    mermaid: bool,
+
+    /// Directory keeping generated files between runs, such as resized images.
+    #[arg(long, default_value_t = String::from("../.cache"))]
+    cache: String,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Generate TypeScript definitions of manifests for the frontend
+    GenTypes {
+        #[arg(short, long, default_value_t = String::from("../src/api/manifest.ts"))]
+        output: String,
+    },
+    /// Check content for errors without writing output, exits with failure if any is found
+    Check {
+        #[arg(default_value_t = String::from("../content"))]
+        input: String,
+    },
+    /// Check external links of blogs and write a report, exits with failure if any is broken
+    CheckLinks {
+        #[arg(default_value_t = String::from("../content"))]
+        input: String,
+
+        #[arg(short, long, default_value_t = String::from("link-report.json"))]
+        report: String,
+
+        /// Results of previous runs, working links are not checked again until they expire.
+        #[arg(long, default_value_t = String::from("link-cache.json"))]
+        cache: String,
+
+        /// Days cached results of working links are kept.
+        #[arg(long, default_value_t = 7)]
+        cache_days: u64,
+
+        /// Seconds to wait for each request.
+        #[arg(long, default_value_t = 10)]
+        timeout: u64,
+
+        /// Number of links checked at the same time.
+        #[arg(long, default_value_t = 8)]
+        concurrency: usize,
+    },
+    /// Decrypt a written blog with password, or its secret sections, and print the html
+    Decrypt {
+        /// Html file of the blog in the output.
+        file: String,
+
+        password: String,
+    },
+    /// Generate JSON Schemas of blog frontmatter and site.toml for editors
+    Schema {
+        #[arg(short, long, default_value_t = String::from("schema"))]
+        output: String,
+    },
 }
 
 fn copy_dir_all(src: impl AsRef<Path>, dst: impl AsRef<Path>) -> io::Result<()> {
@@ -57,8 +156,107 @@ fn copy_dir_all(src: impl AsRef<Path>, dst: impl AsRef<Path>) -> io::Result<()>
     Ok(())
 }
 
+/// Read a previous manifest. An unreadable one is only discarded when regenerating.
+fn read_manifest<T: migration::Versioned>(path: &Path, regenerate: bool) -> T {
+    match migration::load(path) {
+        Ok(manifest) => manifest,
+        Err(e) if regenerate => {
+            eprintln!("\nCannot read manifest {}: {}. Regenerating from scratch.", path.display(), e);
+            T::default()
+        },
+        Err(e) => {
+            eprintln!("\nCannot read manifest {}: {}.", path.display(), e);
+            eprintln!("Fix or remove the manifest, or pass --regenerate to rebuild everything.");
+            process::exit(1)
+        }
+    }
+}
+
+fn write_manifest(path: impl AsRef<Path>, manifest: &impl serde::Serialize, pretty: bool) -> serde_json::Result<()> {
+    let mut file = io::BufWriter::new(fs::File::create(path).map_err(serde_json::Error::io)?);
+    if pretty {
+        serde_json::to_writer_pretty(&mut file, manifest)?;
+    } else {
+        serde_json::to_writer(&mut file, manifest)?;
+    }
+    file.flush().map_err(serde_json::Error::io)
+}
+
 fn main() {
-    let Args { input, output, copyright_name, regenerate, no_archive, no_encrypt } = Args::parse();
+    let Args { command, input, output, copyright_name, mut regenerate, no_archive, no_encrypt, pretty, sharded, page_size, avif, mermaid, cache } = Args::parse();
+    match command {
+        Some(Command::GenTypes { output }) => {
+            fs::write(&output, types::typescript()).unwrap();
+            println!("TypeScript definitions are written to {output}.");
+            return
+        },
+        Some(Command::Schema { output }) => {
+            fs::create_dir_all(&output).unwrap();
+            for (file_name, schema) in [
+                ("frontmatter.schema.json", blog::frontmatter_schema()),
+                ("site.schema.json", schema::toml_schema::<site::Site>()),
+            ] {
+                let mut json = serde_json::to_string_pretty(&schema).unwrap();
+                json.push('\n');
+                fs::write(Path::new(&output).join(file_name), json).unwrap();
+            }
+            println!("JSON Schemas are written to {output}.");
+            return
+        },
+        Some(Command::Check { input }) => {
+            let diagnostics = check::check(&input);
+            for diagnostic in &diagnostics {
+                eprintln!("{diagnostic}");
+            }
+            let errors = diagnostics.iter().filter(|diagnostic| diagnostic.severity == check::Severity::Error).count();
+            println!("Checked {input}: {errors} errors, {} warnings.", diagnostics.len() - errors);
+            if errors > 0 {
+                process::exit(1)
+            }
+            return
+        },
+        Some(Command::CheckLinks { input, report, cache, cache_days, timeout, concurrency }) => {
+            let links = links::external_links(Path::new(&input).join("blogs"));
+            println!("Checking {} external links...", links.len());
+            let client = links::UreqClient::new(Duration::from_secs(timeout));
+            let mut link_cache = links::LinkCache::load(&cache);
+            let max_age = Duration::from_secs(cache_days * 24 * 60 * 60);
+            let link_report = links::check_links(&client, links, &mut link_cache, max_age, concurrency);
+            for result in link_report.links.iter().filter(|result| !result.status.is_ok()) {
+                let blogs: Vec<_> = result.blogs.iter().map(String::as_str).collect();
+                eprintln!("{}: {} (in {})", result.url, result.status, blogs.join(", "));
+            }
+            write_manifest(&cache, &link_cache, true).unwrap();
+            write_manifest(&report, &link_report, true).unwrap();
+            println!("Checked {} links, {} from cache: {} broken. Report is written to {report}.",
+                link_report.links.len(), link_report.links.len() - link_report.checked, link_report.broken);
+            if link_report.broken > 0 {
+                process::exit(1)
+            }
+            return
+        },
+        Some(Command::Decrypt { file, password }) => {
+            let html = fs::read_to_string(&file).unwrap_or_else(|e| {
+                eprintln!("Error happended: {e}");
+                process::exit(1)
+            });
+            let ciphertexts = render::ciphertexts(&html);
+            // secret sections may have passwords of their own, only those unlocked are printed
+            let plaintexts: Vec<_> = ciphertexts.iter()
+                .filter_map(|ciphertext| BASE64_STANDARD.decode(ciphertext).ok())
+                .filter_map(|data| crypto::decrypt(&data, &password).ok())
+                .map(|(plaintext, _)| plaintext)
+                .collect();
+            if plaintexts.is_empty() {
+                eprintln!("Error happended: {} in {file}.", if ciphertexts.is_empty() { "Nothing is encrypted" } else { "Decryption failed" });
+                process::exit(1)
+            }
+            println!("{}", plaintexts.join("\n"));
+            eprintln!("Decrypted {} of {} encrypted parts.", plaintexts.len(), ciphertexts.len());
+            return
+        },
+        None => {},
+    }
     let mut stdout = stdout().lock();
 
     // if regenerate {
@@ -75,12 +273,19 @@ fn main() {
     
     let blogs_path = Path::new(&input).join("blogs");
     let sites_path = Path::new(&input).join("sites");
+    let tags_path = Path::new(&input).join("tags.toml");
+    let keyring_path = Path::new(&input).join("keyring.toml");
     let output_path = Path::new(&output);
     let dst_blogs_path = output_path.join("blogs");
     let dst_sites_path = output_path.join("sites");
     let apis_path = output_path.join("api");
     let blog_manifest_path = apis_path.join("blog-manifest.json");
     let site_manifest_path = apis_path.join("site-manifest.json");
+    let images_cache_path = Path::new(&cache).join("images");
+    let diagrams_cache_path = Path::new(&cache).join("diagrams");
+    let keyring_fingerprint_path = Path::new(&cache).join("keyring-fingerprint");
+    let links_cache_path = Path::new(&cache).join("links.json");
+    let images_index_path = Path::new(&cache).join("images.json");
 
     println!("Creating required directories...");
     fs::create_dir_all(&blogs_path).unwrap();
@@ -107,17 +312,72 @@ fn main() {
     println!("done.");
     print!("Reading previous manifests...");
     stdout.flush().unwrap();
-    let mut blog_manifest = fs::File::open(&blog_manifest_path).ok().and_then(|s| {
-        serde_json::from_reader::<fs::File, BlogManifest>(s).ok()
-    }).unwrap_or_default();
-    
-    let mut site_manifest = fs::File::open(&site_manifest_path).ok().and_then(|s| {
-        serde_json::from_reader::<fs::File, SiteManifest>(s).ok()
-    }).unwrap_or_default();
+    let mut blog_manifest = read_manifest::<BlogManifest>(&blog_manifest_path, regenerate);
+    let mut site_manifest = read_manifest::<SiteManifest>(&site_manifest_path, regenerate);
+    println!("done.");
+
+    print!("Reading links between blogs...");
+    stdout.flush().unwrap();
+    // anchors and references of blogs are only kept in the cache, without them links are not validated
+    // and blogs linking to updated ones are not rendered again
+    match fs::read_to_string(&links_cache_path).ok().and_then(|links| serde_json::from_str(&links).ok()) {
+        Some(links) => blog_manifest.set_links(links),
+        None if !blog_manifest.blogs.is_empty() => {
+            print!("not found, regenerating all blogs...");
+            regenerate = true;
+        },
+        None => {},
+    }
+    println!("done.");
+
+    print!("Reading tag definitions...");
+    stdout.flush().unwrap();
+    let tag_registry = match TagRegistry::load(&tags_path) {
+        Ok(tag_registry) => tag_registry,
+        Err(e) => {
+            eprintln!("Error happended: {}\n. No tag is declared.", e);
+            TagRegistry::default()
+        }
+    };
+    if tag_registry.0 != blog_manifest.tag_metadata {
+        // tags of every blog shall be normalized again
+        print!("changed, regenerating all blogs...");
+        regenerate = true;
+        blog_manifest.tag_metadata = tag_registry.0.clone();
+    }
+    println!("done.");
+
+    print!("Reading keyring...");
+    stdout.flush().unwrap();
+    let keyring = match Keyring::load(&keyring_path) {
+        Ok(keyring) => keyring,
+        Err(e) => {
+            eprintln!("Error happended: {}\n. No key is declared.", e);
+            Keyring::default()
+        }
+    };
+    // blogs with password are encrypted the same way as long as the seed is kept in the cache
+    let seed_path = Path::new(&cache).join("seed");
+    if !fs::exists(&seed_path).unwrap_or(false) {
+        eprintln!("\nWarning: no seed is found in {}, a new one is generated and every blog with password is encrypted anew. \
+            Keep the cache between builds, so that ciphertexts only change when their content does.", seed_path.display());
+    }
+    let seed = crypto::load_seed(&seed_path).unwrap_or_else(|e| {
+        eprintln!("Error happended: {e}\n. Blogs with password are encrypted differently in each build.");
+        crypto::random_seed()
+    });
+    // passwords of any blog may be changed in the file or the environment, and they are never published
+    let previous_fingerprint = fs::read_to_string(&keyring_fingerprint_path).ok();
+    let keyring_fingerprint = keyring.fingerprint(previous_fingerprint.as_deref());
+    if previous_fingerprint.as_ref() != Some(&keyring_fingerprint) {
+        print!("changed, regenerating all blogs...");
+        regenerate = true;
+    }
     println!("done.");
 
     let mut updated_blogs = vec![];
-    
+    let mut previous_series = HashSet::new();
+
 
     for blog in fs::read_dir(&blogs_path).unwrap() {
         let blog_entry = blog.unwrap();
@@ -134,9 +394,15 @@ fn main() {
         let os_modified = blog_metadata.modified().unwrap();
 
         let res = match blog_manifest.blogs.entry(id.clone()) {
-            std::collections::hash_map::Entry::Occupied(mut occupied_entry) => occupied_entry.get_mut().update(blog_entry.path(), os_created, os_modified, regenerate),
-            std::collections::hash_map::Entry::Vacant(vacant_entry) => Blog::parse(blog_entry.path(), os_created, os_modified)
-                .and_then(|(blog, pwd)| { vacant_entry.insert(blog); Ok(Some(pwd)) }),
+            std::collections::btree_map::Entry::Occupied(mut occupied_entry) => {
+                // the blog may leave its series, whose remaining parts need the new listing.
+                if let Some(series) = &occupied_entry.get().series {
+                    previous_series.insert(series.clone());
+                }
+                occupied_entry.get_mut().update(blog_entry.path(), os_created, os_modified, &tag_registry, &keyring, regenerate)
+            },
+            std::collections::btree_map::Entry::Vacant(vacant_entry) => Blog::parse(blog_entry.path(), os_created, os_modified, &tag_registry, &keyring)
+                .map(|(blog, pwd)| { vacant_entry.insert(blog); Some(pwd) }),
         };
         match res {
             Ok(Some(pwd)) => { updated_blogs.push((id, pwd)); },
@@ -148,21 +414,138 @@ fn main() {
         }
         println!("done.");
     }
-    println!("All blog manifest are read. Total blogs: {}, needs update: {}", blog_manifest.blogs.len(), updated_blogs.len());
+    // blogs removed from the content directory
+    let removed_blogs: Vec<_> = blog_manifest.blogs.keys()
+        .filter(|id| !fs::exists(blogs_path.join(id)).unwrap())
+        .cloned()
+        .collect();
+    for id in &removed_blogs {
+        print!("Removing blog {id}...");
+        stdout.flush().unwrap();
+        let blog = blog_manifest.blogs.remove(id).unwrap();
+        if let Some(series) = blog.series {
+            previous_series.insert(series);
+        }
+        for tag_blogs in blog_manifest.tags.values_mut() {
+            tag_blogs.remove(id);
+        }
+        for series_blogs in blog_manifest.series.values_mut() {
+            series_blogs.remove(id);
+        }
+        let archive_name = id.strip_suffix(".md").unwrap();
+        // don't care with removing results.
+        let _ = fs::remove_file(dst_blogs_path.join(archive_name.to_string() + ".html"));
+        let _ = fs::remove_file(dst_blogs_path.join(archive_name.to_string() + ".zip"));
+        let _ = fs::remove_dir_all(dst_blogs_path.join(archive_name.to_string() + ".assets"));
+        println!("done.");
+    }
+
+    println!("All blog manifest are read. Total blogs: {}, needs update: {}, removed: {}", blog_manifest.blogs.len(), updated_blogs.len(), removed_blogs.len());
+    // even for blogs not updated, since the password is in the content directory all along
+    for (id, blog) in &blog_manifest.blogs {
+        if blog.literal_password {
+            eprintln!("Warning: blog {id} has its password in the frontmatter, move it to keyring.toml and use password_ref.");
+        }
+    }
     // calculate tags
     for (id, _) in &updated_blogs {
         let blog = blog_manifest.blogs.get(id).unwrap();
+        for tag in tag_registry.unknown(&blog.tags) {
+            eprintln!("Warning: blog {id} has tag \"{tag}\" not declared in tags.toml.");
+        }
         for tag_blogs in blog_manifest.tags.values_mut() {
             tag_blogs.remove(id);
         }
         for tag in &blog.tags {
-            blog_manifest.tags.entry(tag.clone()).or_insert(HashSet::new()).insert(id.clone());
+            blog_manifest.tags.entry(tag.clone()).or_default().insert(id.clone());
+        }
+    }
+    blog_manifest.tags.retain(|_, tag_blogs| !tag_blogs.is_empty());
+    // pages of tags sharing a file name would overwrite each other
+    let clashes = blog::tag::slug_clashes(blog_manifest.tags.keys().map(String::as_str));
+    for tags in &clashes {
+        eprintln!("Error happended: tags \"{}\" have the same file name, rename all but one of them.", tags.join("\", \""));
+    }
+    if !clashes.is_empty() {
+        process::exit(1)
+    }
+
+    // calculate series
+    let mut touched_series = previous_series;
+    for (id, _) in &updated_blogs {
+        let blog = blog_manifest.blogs.get(id).unwrap();
+        for series_blogs in blog_manifest.series.values_mut() {
+            series_blogs.remove(id);
+        }
+        if let Some(series) = &blog.series {
+            blog_manifest.series.entry(series.clone()).or_default().insert(id.clone());
+            touched_series.insert(series.clone());
         }
     }
+    blog_manifest.series.retain(|_, series_blogs| !series_blogs.is_empty());
+
+    // calculate navigation
+    let navigation = blog_manifest.navigation();
+    let changed: HashSet<_> = updated_blogs.iter().map(|(id, _)| id).chain(&removed_blogs).collect();
+
+    // other parts of updated series shall be rendered again for new listing and links,
+    // as well as blogs whose navigation changes, or which link to a changed blog.
+    let affected: BTreeSet<_> = touched_series.iter()
+        .filter_map(|series| blog_manifest.series.get(series))
+        .flatten()
+        .chain(navigation.iter()
+            .filter(|(id, nav)| blog_manifest.blogs[*id].navigation != **nav || nav.links().any(|link| changed.contains(link)))
+            .map(|(id, _)| id))
+        .chain(blog_manifest.blogs.iter()
+            .filter(|(_, blog)| blog.references.iter().any(|reference| changed.contains(reference)))
+            .map(|(id, _)| id))
+        .filter(|id| !changed.contains(id))
+        .cloned()
+        .collect();
+    for id in affected {
+        print!("Reading affected blog {id}...");
+        stdout.flush().unwrap();
+        let path = blogs_path.join(&id);
+        let res = fs::metadata(&path).map_err(Into::into).and_then(|metadata| {
+            blog_manifest.blogs.get_mut(&id).unwrap().update(&path, metadata.created()?, metadata.modified()?, &tag_registry, &keyring, true)
+        });
+        match res {
+            Ok(Some(pwd)) => updated_blogs.push((id, pwd)),
+            Ok(None) => {}
+            Err(e) => {
+                eprintln!("Error happended: {}\n. Skipped.", e);
+                continue
+            }
+        }
+        println!("done.");
+    }
+    for (id, nav) in navigation {
+        blog_manifest.blogs.get_mut(&id).unwrap().navigation = nav;
+    }
+
+    // render before writing any blog, so that links between updated blogs are validated with their headings
+    let mut rendered_blogs = BTreeMap::new();
+    for (id, _) in &updated_blogs {
+        let blog_content = fs::read_to_string(blogs_path.join(id)).unwrap();
+        let rendered = render::render(&blog_content);
+        let blog = blog_manifest.blogs.get_mut(id).unwrap();
+        blog.headings = (!blog.password).then(|| rendered.headings.clone());
+        rendered_blogs.insert(id.clone(), rendered);
+    }
+
+    let mut diagram_renderers: Vec<Box<dyn diagrams::DiagramRenderer>> = vec![Box::new(diagrams::CommandRenderer::graphviz())];
+    if mermaid {
+        diagram_renderers.push(Box::new(diagrams::CommandRenderer::mermaid()));
+    }
+
+    // entries of the image cache used by each blog, those used by none are removed after the build
+    let mut cached_images: BTreeMap<String, BTreeSet<String>> = fs::read_to_string(&images_index_path).ok()
+        .and_then(|index| serde_json::from_str(&index).ok())
+        .unwrap_or_default();
 
     // calculate preview, also copy files
-    for (id, pwd) in updated_blogs {
-        let blog = blog_manifest.blogs.get_mut(&id).unwrap();
+    for (id, passwords) in updated_blogs {
+        cached_images.remove(&id);
 
         let archive_name = id.strip_suffix(".md").unwrap().to_string();
 
@@ -178,56 +561,180 @@ fn main() {
         // don't care with removing results.
         let _ = fs::remove_file(dst_blogs_path.join(&id));
         let _ = fs::remove_file(dst_blogs_path.join(&archive_name_zipped));
-        let _ = fs::remove_dir_all(output_path.join(&assets));
+        let _ = fs::remove_dir_all(dst_blogs_path.join(&assets));
         let _ = fs::remove_dir_all(&archive_path);
 
+        // assets of blogs with password are encrypted with the same key
+        let key = (!passwords.is_empty() && !no_encrypt).then(|| {
+            match blog_manifest.blogs[&id].remember {
+                true => crypto::Key::shared(&passwords, &seed, &[id.as_bytes()]),
+                false => crypto::Key::new(&passwords, &seed, &[id.as_bytes()]),
+            }
+        });
+        let render::Rendered { html: rendered_html, preview, headings, mut diagnostics, secrets, .. } = rendered_blogs.remove(&id).unwrap();
+        // secret sections are encrypted with their own password, even in blogs with password
+        let section_keys: Vec<_> = secrets.iter().map(|secret| (!no_encrypt).then(|| {
+            if secret.password.is_some() {
+                eprintln!("\n    Warning: blog {id} has the password of a secret section written in it, use password_ref.");
+            }
+            let password = match (&secret.password_ref, &secret.password) {
+                (Some(name), _) => keyring.get(name),
+                (None, Some(password)) => Ok(password.clone()),
+                (None, None) => Err("secret section has no password_ref".to_string()),
+            };
+            password.map(|password| {
+                let section_passwords = [password];
+                let context = [id.as_bytes(), b"secret"];
+                match blog_manifest.blogs[&id].remember {
+                    true => crypto::Key::shared(&section_passwords, &seed, &context),
+                    false => crypto::Key::new(&section_passwords, &seed, &context),
+                }
+            })
+        })).collect();
+        let mut images = BTreeMap::new();
+        let mut encrypted_assets = BTreeMap::new();
+        let mut section_assets = vec![BTreeMap::new(); secrets.len()];
         if fs::exists(blogs_path.join(&assets)).unwrap() {
             copy_dir_all(blogs_path.join(&assets), dst_blogs_path.join(&assets)).unwrap();
+            if !blog_manifest.blogs[&id].keep_image_metadata {
+                match metadata::strip_dir(&dst_blogs_path.join(&assets)) {
+                    Ok(failed) => diagnostics.extend(failed.into_iter().map(|failed| failed + ", it is left out")),
+                    Err(e) => {
+                        // never publish what may still have its metadata
+                        if let Err(removal) = fs::remove_dir_all(dst_blogs_path.join(&assets)) {
+                            eprintln!("\n    Error happended: {e}, and assets of blog {id} cannot be removed: {removal}.");
+                            process::exit(1);
+                        }
+                        diagnostics.push(format!("{e}, assets are left out"));
+                    },
+                }
+            }
+            if !fs::exists(dst_blogs_path.join(&assets)).unwrap() {
+                // left out entirely
+            } else if let Some(key) = &key {
+                encrypted_assets = key.encrypt_assets(&dst_blogs_path.join(&assets)).unwrap();
+            } else {
+                // assets only used in secret sections of a public blog are encrypted with their keys,
+                // those of sections left out are left out as well
+                let public_assets = render::asset_paths(&rendered_html, &assets);
+                let mut secret_assets = BTreeSet::new();
+                for ((secret, section_key), names) in secrets.iter().zip(&section_keys).zip(&mut section_assets) {
+                    let paths: BTreeSet<_> = render::asset_paths(&secret.html, &assets).into_iter()
+                        .filter(|path| !public_assets.contains(path) && dst_blogs_path.join(&assets).join(path).is_file())
+                        .collect();
+                    if let Some(Ok(section_key)) = section_key {
+                        *names = section_key.encrypt_files(&dst_blogs_path.join(&assets), &paths).unwrap();
+                    }
+                    secret_assets.extend(paths);
+                }
+                for path in &secret_assets {
+                    fs::remove_file(dst_blogs_path.join(&assets).join(path)).unwrap();
+                }
+                let processed = images::process_assets(&blogs_path.join(&assets), &dst_blogs_path.join(&assets), &images_cache_path, avif)
+                    .unwrap_or_else(|e| {
+                        eprintln!("\n    Warning: {e}, images are not resized.");
+                        images::Assets::default()
+                    });
+                images = processed.images;
+                cached_images.insert(id.clone(), processed.cached);
+            }
         }
 
-        let blog_content = fs::read_to_string(blogs_path.join(&id)).unwrap();
-        let (rendered_blog_content, preview) = render::render(&blog_content);
+        // anchors of secret sections are only in the page, not in the manifest
+        let headings: Vec<_> = headings.into_iter().chain(secrets.iter().flat_map(|secret| secret.headings.clone())).collect();
+        let render::ResolvedLinks { html: rendered_blog_content, mut references, diagnostics: mut link_diagnostics } = render::resolve_links(&rendered_html, &id, &headings, &blog_manifest);
+        let secret_htmls: Vec<_> = secrets.iter().map(|secret| {
+            let resolved = render::resolve_links(&secret.html, &id, &headings, &blog_manifest);
+            link_diagnostics.extend(resolved.diagnostics);
+            for reference in resolved.references {
+                if !references.contains(&reference) {
+                    references.push(reference);
+                }
+            }
+            resolved.html
+        }).collect();
+        blog_manifest.blogs.get_mut(&id).unwrap().references = references;
+        let rewrite_assets = |html: &str| match key {
+            Some(_) => render::encrypted_assets(html, &assets, &encrypted_assets),
+            None => render::responsive_images(html, &assets, &images),
+        };
+        let mut draw_diagrams = |html: &str| {
+            let (html, diagram_diagnostics) = diagrams::render_diagrams(html, &diagram_renderers, &diagrams_cache_path);
+            diagnostics.extend(diagram_diagnostics);
+            html
+        };
+        let rendered_blog_content = draw_diagrams(&rewrite_assets(&rendered_blog_content));
+
+        let sections: Vec<_> = secrets.iter().zip(&secret_htmls).zip(section_keys.iter().zip(&section_assets)).map(|((secret, html), (section_key, names))| {
+            let html = draw_diagrams(&render::encrypted_assets(&rewrite_assets(html), &assets, names));
+            match section_key {
+                None => format!(r#"<div class="secret unlocked">{html}</div>"#),
+                Some(Ok(section_key)) => {
+                    let encrypted = section_key.encrypt(html.as_bytes());
+                    render::save_secret_section(secret.hint.as_deref(), BASE64_STANDARD.encode(encrypted), &section_key.key_ids())
+                },
+                Some(Err(e)) => {
+                    eprintln!("\n    Error happended: {e}, the section is left out.");
+                    r#"<div class="secret"></div>"#.to_string()
+                },
+            }
+        }).collect();
+        let mut rendered_blog_content = render::fill_secrets(&rendered_blog_content, &sections);
+        if !secrets.is_empty() && key.is_none() && !no_encrypt {
+            rendered_blog_content.push_str(r#"<script defer src="decrypt.js"></script>"#);
+        }
+        if !diagnostics.is_empty() || !link_diagnostics.is_empty() {
+            println!();
+        }
+        for diagnostic in diagnostics.into_iter().chain(link_diagnostics) {
+            eprintln!("    Warning: {diagnostic}");
+        }
 
         let mut dst_blog = fs::File::create(dst_blogs_path.join(&html)).unwrap();
 
-        if let Some(password) = pwd.and_then(|pwd| (!no_encrypt).then_some(pwd)) {
+        if let Some(key) = key {
             print!("\n    Blog {id} needs encryption. encrypting...");
             stdout.flush().unwrap();
             
-            let encrypted = crypto::encrypt_data(&rendered_blog_content, &password);
+            let payload = match &blog_manifest.blogs[&id].private {
+                Some(private) => render::private_metadata_html(&blog_manifest, private) + &rendered_blog_content,
+                None => rendered_blog_content,
+            };
+            let encrypted = key.encrypt(payload.as_bytes());
             let encoded = BASE64_STANDARD.encode(encrypted);
-            dst_blog.write(save_html_secret(blog, encoded, &copyright_name).as_bytes()).unwrap();
+            dst_blog.write_all(save_html_secret(&blog_manifest, &id, encoded, &key.key_ids(), &copyright_name).as_bytes()).unwrap();
             println!("done.");
         } else {
-            blog.preview = preview;
-            dst_blog.write(save_html(blog, rendered_blog_content, &copyright_name).as_bytes()).unwrap();
-            if let Some(license) = &blog.license {
-                if license.is_permissive() && !no_archive {
-                    // allow downloading and archive
-                    print!("\n    Creating archive for blog {id} due to permissive license...");
-                    stdout.flush().unwrap();
-                    fs::create_dir_all(&archive_path).unwrap();
-                    
-                    fs::copy(blogs_path.join(&id), archive_path.join(&id)).unwrap();
-                    
-                    // create license
-
-                    let mut archive_license = fs::File::create(archive_path.join("LICENSE.txt")).unwrap();
-                    archive_license.write(license.license_text(&id, blog.get_copyright_year(), &copyright_name).as_bytes()).unwrap();
-
-                    let success = process::Command::new("zip")
-                        .current_dir(&dst_blogs_path)
-                        .arg("-r")
-                        .arg("-m")
-                        .arg(archive_name_zipped)
-                        .arg(archive_name)
-                        .stdout(process::Stdio::null())
-                        .status().unwrap().success();
-                    if !success {
-                        panic!("failed to create archive")
-                    }
-                    println!("done.");
+            blog_manifest.blogs.get_mut(&id).unwrap().preview = preview;
+            let blog = blog_manifest.blogs.get(&id).unwrap();
+            dst_blog.write_all(save_html(&blog_manifest, &id, rendered_blog_content, &copyright_name).as_bytes()).unwrap();
+            // the archive has the source, secret sections included
+            if let Some(license) = &blog.license
+                && license.is_permissive() && !no_archive && secrets.is_empty() {
+                // allow downloading and archive
+                print!("\n    Creating archive for blog {id} due to permissive license...");
+                stdout.flush().unwrap();
+                fs::create_dir_all(&archive_path).unwrap();
+                
+                fs::copy(blogs_path.join(&id), archive_path.join(&id)).unwrap();
+                
+                // create license
+
+                let mut archive_license = fs::File::create(archive_path.join("LICENSE.txt")).unwrap();
+                archive_license.write_all(license.license_text(&id, blog.get_copyright_year(), &copyright_name).as_bytes()).unwrap();
+
+                let success = process::Command::new("zip")
+                    .current_dir(&dst_blogs_path)
+                    .arg("-r")
+                    .arg("-m")
+                    .arg(archive_name_zipped)
+                    .arg(archive_name)
+                    .stdout(process::Stdio::null())
+                    .status().unwrap().success();
+                if !success {
+                    panic!("failed to create archive")
                 }
+                println!("done.");
             }
         }
         println!("done.");
@@ -235,7 +742,53 @@ fn main() {
 
     print!("Writing blog manifest to file...");
     stdout.flush().unwrap();
-    serde_json::to_writer(fs::File::create(&blog_manifest_path).unwrap(), &blog_manifest).unwrap();
+    write_manifest(&blog_manifest_path, &blog_manifest, pretty).unwrap();
+    cached_images.retain(|id, _| blog_manifest.blogs.contains_key(id));
+    let _ = images::prune_cache(&images_cache_path, &cached_images.values().flatten().cloned().collect());
+    // failing to keep them in the cache regenerates the next build
+    let _ = fs::create_dir_all(&cache).and_then(|_| {
+        fs::write(&images_index_path, serde_json::to_string(&cached_images).unwrap())?;
+        fs::write(&links_cache_path, serde_json::to_string(&blog_manifest.links()).unwrap())?;
+        // only once every blog is encrypted with the passwords
+        fs::write(&keyring_fingerprint_path, &keyring_fingerprint)
+    });
+    println!("done.");
+
+    if sharded {
+        print!("Writing sharded blog manifest...");
+        stdout.flush().unwrap();
+        for dir in ["blogs", "tags", "posts"] {
+            let _ = fs::remove_dir_all(apis_path.join(dir));
+        }
+        let shards = blog_manifest.shards(page_size);
+        fs::create_dir_all(apis_path.join("blogs")).unwrap();
+        write_manifest(apis_path.join("blogs").join("index.json"), &shards.index, pretty).unwrap();
+        for (path, page) in &shards.pages {
+            fs::create_dir_all(apis_path.join(path).parent().unwrap()).unwrap();
+            write_manifest(apis_path.join(path), page, pretty).unwrap();
+        }
+        for (path, blog) in &shards.blogs {
+            fs::create_dir_all(apis_path.join(path).parent().unwrap()).unwrap();
+            write_manifest(apis_path.join(path), blog, pretty).unwrap();
+        }
+        println!("done.");
+    }
+
+    // listing pages depend on every blog, so they are always generated again.
+    print!("Generating tag and archive pages...");
+    stdout.flush().unwrap();
+    let dst_tags_path = dst_blogs_path.join("tags");
+    let dst_archive_path = dst_blogs_path.join("archive");
+    let _ = fs::remove_dir_all(&dst_tags_path);
+    let _ = fs::remove_dir_all(&dst_archive_path);
+    fs::create_dir_all(&dst_tags_path).unwrap();
+    fs::create_dir_all(&dst_archive_path).unwrap();
+    for (file_name, html) in render::save_tags_html(&blog_manifest) {
+        fs::write(dst_tags_path.join(file_name), html).unwrap();
+    }
+    for (file_name, html) in render::save_archive_html(&blog_manifest) {
+        fs::write(dst_archive_path.join(file_name), html).unwrap();
+    }
     println!("done.");
     
     
@@ -260,8 +813,8 @@ fn main() {
                 continue
             }
         };
-        site_manifest.0.insert(id, site);
+        site_manifest.sites.insert(id, site);
     }
 
-    serde_json::to_writer(fs::File::create(&site_manifest_path).unwrap(), &site_manifest).unwrap();
+    write_manifest(&site_manifest_path, &site_manifest, pretty).unwrap();
 }
@@ -0,0 +1,366 @@
+use std::error::Error;
+use std::{fs, io, path::Path};
+
+use crate::images::Format;
+
+/// EXIF tag of the orientation, the only metadata kept since it changes how images are displayed.
+const ORIENTATION: u16 = 0x0112;
+
+/// Orientation other than the default in EXIF data, which starts with a TIFF header.
+fn exif_orientation(tiff: &[u8]) -> Option<u16> {
+    let big_endian = match tiff.get(..4)? {
+        b"MM\0*" => true,
+        b"II*\0" => false,
+        _ => return None,
+    };
+    let u16_at = |i: usize| tiff.get(i..i + 2).map(|b| match big_endian {
+        true => u16::from_be_bytes([b[0], b[1]]),
+        false => u16::from_le_bytes([b[0], b[1]]),
+    });
+    let u32_at = |i: usize| tiff.get(i..i + 4).map(|b| match big_endian {
+        true => u32::from_be_bytes([b[0], b[1], b[2], b[3]]),
+        false => u32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+    });
+    let ifd = u32_at(4)? as usize;
+    let entries = u16_at(ifd)? as usize;
+    (0..entries).map(|i| ifd + 2 + i * 12)
+        .find(|&entry| u16_at(entry) == Some(ORIENTATION))
+        .and_then(|entry| u16_at(entry + 8))
+        .filter(|&orientation| orientation != 1)
+}
+
+/// EXIF data with nothing but the orientation.
+fn orientation_exif(orientation: u16) -> Vec<u8> {
+    let mut tiff = b"MM\0*".to_vec();
+    tiff.extend(8u32.to_be_bytes());
+    tiff.extend(1u16.to_be_bytes());
+    // tag, type SHORT, count, value padded to 4 bytes
+    tiff.extend(ORIENTATION.to_be_bytes());
+    tiff.extend(3u16.to_be_bytes());
+    tiff.extend(1u32.to_be_bytes());
+    tiff.extend(orientation.to_be_bytes());
+    tiff.extend([0, 0]);
+    // no next IFD
+    tiff.extend(0u32.to_be_bytes());
+    tiff
+}
+
+/// Keep segments needed to decode a JPEG, which are all but APP1 to APP15 except ICC profiles and
+/// Adobe color transforms, and comments. Images appended after the end, such as those described in
+/// multi-picture format, are dropped as well.
+fn strip_jpeg(bytes: &[u8]) -> Result<Vec<u8>, &'static str> {
+    const INVALID: &str = "invalid JPEG";
+    if !bytes.starts_with(&[0xFF, 0xD8]) {
+        return Err(INVALID)
+    }
+    let mut segments = vec![];
+    let mut orientation = None;
+    let mut i = 2;
+    let scan = loop {
+        match bytes.get(i..i + 2).ok_or(INVALID)? {
+            [0xFF, 0xFF] => i += 1,
+            [0xFF, 0x01 | 0xD0..=0xD7] => {
+                segments.push(&bytes[i..i + 2]);
+                i += 2;
+            },
+            // start of scan, segments after are image data
+            [0xFF, 0xDA] => break &bytes[i..],
+            [0xFF, marker] => {
+                let length = bytes.get(i + 2..i + 4).ok_or(INVALID)?;
+                let end = i + 2 + u16::from_be_bytes([length[0], length[1]]) as usize;
+                let segment = bytes.get(i..end).ok_or(INVALID)?;
+                let payload = &segment[4.min(segment.len())..];
+                let keep = match marker {
+                    0xE1 => {
+                        if let Some(tiff) = payload.strip_prefix(b"Exif\0\0") {
+                            orientation = orientation.or(exif_orientation(tiff));
+                        }
+                        false
+                    },
+                    0xE2 => payload.starts_with(b"ICC_PROFILE\0"),
+                    0xE3..=0xED | 0xEF | 0xFE => false,
+                    _ => true,
+                };
+                if keep {
+                    segments.push(segment);
+                }
+                i = end;
+            },
+            _ => return Err(INVALID),
+        }
+    };
+    // markers cannot appear in entropy coded data, where 0xFF is always followed by 0x00
+    let end = scan.windows(2).position(|w| w == [0xFF, 0xD9]).ok_or(INVALID)? + 2;
+
+    let mut output = vec![0xFF, 0xD8];
+    // JFIF header must come first
+    let jfif = segments.first().is_some_and(|segment| segment[1] == 0xE0) as usize;
+    for segment in &segments[..jfif] {
+        output.extend(*segment);
+    }
+    if let Some(orientation) = orientation {
+        let exif = [b"Exif\0\0".as_slice(), &orientation_exif(orientation)].concat();
+        output.extend([0xFF, 0xE1]);
+        output.extend((exif.len() as u16 + 2).to_be_bytes());
+        output.extend(exif);
+    }
+    for segment in &segments[jfif..] {
+        output.extend(*segment);
+    }
+    output.extend(&scan[..end]);
+    Ok(output)
+}
+
+fn png_chunk(kind: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut chunk = (data.len() as u32).to_be_bytes().to_vec();
+    chunk.extend(kind);
+    chunk.extend(data);
+    chunk.extend(crc32fast::hash(&chunk[4..]).to_be_bytes());
+    chunk
+}
+
+/// Drop EXIF, text chunks where XMP is kept, and modification time of a PNG.
+fn strip_png(bytes: &[u8]) -> Result<Vec<u8>, &'static str> {
+    const INVALID: &str = "invalid PNG";
+    const SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+    if !bytes.starts_with(SIGNATURE) {
+        return Err(INVALID)
+    }
+    let mut chunks = vec![];
+    let mut orientation = None;
+    let mut i = SIGNATURE.len();
+    while i < bytes.len() {
+        let length = bytes.get(i..i + 4).ok_or(INVALID)?;
+        let end = i + 12 + u32::from_be_bytes([length[0], length[1], length[2], length[3]]) as usize;
+        let chunk = bytes.get(i..end).ok_or(INVALID)?;
+        match &chunk[4..8] {
+            b"eXIf" => orientation = orientation.or(exif_orientation(&chunk[8..chunk.len() - 4])),
+            b"tEXt" | b"zTXt" | b"iTXt" | b"tIME" => {},
+            _ => chunks.push(chunk),
+        }
+        i = end;
+    }
+    let mut output = SIGNATURE.to_vec();
+    for (index, chunk) in chunks.into_iter().enumerate() {
+        output.extend(chunk);
+        // right after IHDR, which must be before image data
+        if index == 0 && let Some(orientation) = orientation {
+            output.extend(png_chunk(b"eXIf", &orientation_exif(orientation)));
+        }
+    }
+    Ok(output)
+}
+
+fn webp_chunk(kind: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut chunk = kind.to_vec();
+    chunk.extend((data.len() as u32).to_le_bytes());
+    chunk.extend(data);
+    if data.len() % 2 == 1 {
+        chunk.push(0);
+    }
+    chunk
+}
+
+/// Drop EXIF and XMP chunks of a WebP, along with their flags in the extended header.
+fn strip_webp(bytes: &[u8]) -> Result<Vec<u8>, &'static str> {
+    const INVALID: &str = "invalid WebP";
+    const EXIF_FLAG: u8 = 0x08;
+    const XMP_FLAG: u8 = 0x04;
+    if bytes.len() < 12 || &bytes[..4] != b"RIFF" || &bytes[8..12] != b"WEBP" {
+        return Err(INVALID)
+    }
+    let mut chunks = vec![];
+    let mut orientation = None;
+    let mut i = 12;
+    while i < bytes.len() {
+        let size = bytes.get(i + 4..i + 8).ok_or(INVALID)?;
+        let size = u32::from_le_bytes([size[0], size[1], size[2], size[3]]) as usize;
+        let end = i + 8 + size + size % 2;
+        let chunk = bytes.get(i..end.min(bytes.len())).ok_or(INVALID)?;
+        let data = chunk.get(8..8 + size).ok_or(INVALID)?;
+        match &chunk[..4] {
+            b"EXIF" => {
+                let tiff = data.strip_prefix(b"Exif\0\0").unwrap_or(data);
+                orientation = orientation.or(exif_orientation(tiff));
+            },
+            b"XMP " => {},
+            _ => chunks.push(chunk.to_vec()),
+        }
+        i = end;
+    }
+    if let Some(header) = chunks.first_mut().filter(|chunk| chunk.starts_with(b"VP8X")) {
+        header[8] &= !(EXIF_FLAG | XMP_FLAG);
+        if orientation.is_some() {
+            header[8] |= EXIF_FLAG;
+        }
+    }
+    // EXIF comes after image data
+    if let Some(orientation) = orientation
+        && chunks.first().is_some_and(|chunk| chunk.starts_with(b"VP8X")) {
+        chunks.push(webp_chunk(b"EXIF", &orientation_exif(orientation)));
+    }
+    let body = chunks.concat();
+    let mut output = b"RIFF".to_vec();
+    output.extend((body.len() as u32 + 4).to_le_bytes());
+    output.extend(b"WEBP");
+    output.extend(body);
+    Ok(output)
+}
+
+/// Remove metadata which may reveal where and with what an image is taken, such as location and
+/// camera, keeping the orientation. Image data is kept as is.
+pub fn strip_metadata(bytes: &[u8], format: Format) -> Result<Vec<u8>, &'static str> {
+    match format {
+        Format::Jpeg => strip_jpeg(bytes),
+        Format::Png => strip_png(bytes),
+        Format::Webp => strip_webp(bytes),
+        Format::Avif => Err("metadata of AVIF cannot be removed"),
+    }
+}
+
+/// Remove metadata of images in a directory in place, returning what cannot be processed. Images
+/// which cannot be processed are removed, as are directories which cannot be read, so that they are
+/// never published with their metadata. It fails when any of them cannot be removed.
+pub fn strip_dir(dir: &Path) -> Result<Vec<String>, String> {
+    let mut failed = vec![];
+    let entries = match fs::read_dir(dir).and_then(|entries| entries.collect::<io::Result<Vec<_>>>()) {
+        Ok(entries) => entries,
+        Err(e) => {
+            fs::remove_dir_all(dir).map_err(|removal| format!("{} cannot be read: {e}, nor removed: {removal}", dir.display()))?;
+            return Ok(vec![format!("{} cannot be read: {e}", dir.display())])
+        },
+    };
+    for entry in entries {
+        let path = entry.path();
+        if path.is_dir() {
+            failed.extend(strip_dir(&path)?);
+            continue
+        }
+        let Some(format) = path.extension().and_then(|extension| Format::from_extension(&extension.to_string_lossy())) else {
+            continue
+        };
+        let stripped: Result<_, Box<dyn Error>> = fs::read(&path).map_err(Into::into)
+            .and_then(|bytes| Ok(strip_metadata(&bytes, format)?))
+            .and_then(|stripped| Ok(fs::write(&path, stripped)?));
+        if let Err(e) = stripped {
+            fs::remove_file(&path).map_err(|removal| format!("metadata cannot be removed from {}: {e}, nor the file: {removal}", path.display()))?;
+            failed.push(format!("metadata cannot be removed from {}: {e}", path.display()));
+        }
+    }
+    Ok(failed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+    use image::{DynamicImage, ImageDecoder, ImageReader, metadata::Orientation};
+    use std::io::Cursor;
+
+    /// Latitude of 48°51'24" in the GPS IFD of `exif`, as little endian rationals.
+    fn latitude() -> Vec<u8> {
+        [48u32, 1, 51, 1, 2400, 100].iter().flat_map(|n| n.to_le_bytes()).collect()
+    }
+
+    /// EXIF in little endian with the orientation, and a GPS IFD with the latitude.
+    fn exif(orientation: u16) -> Vec<u8> {
+        let entry = |tag: u16, kind: u16, count: u32, value: [u8; 4]| {
+            [tag.to_le_bytes().as_slice(), &kind.to_le_bytes(), &count.to_le_bytes(), &value].concat()
+        };
+        // IFD0 at 8 and GPS IFD right after, each with 2 entries, followed by the latitude
+        let gps = 8 + 2 + 2 * 12 + 4;
+        let rationals = gps + 2 + 2 * 12 + 4;
+        let mut tiff = b"II*\0".to_vec();
+        tiff.extend(8u32.to_le_bytes());
+        tiff.extend(2u16.to_le_bytes());
+        tiff.extend(entry(ORIENTATION, 3, 1, (orientation as u32).to_le_bytes()));
+        tiff.extend(entry(0x8825, 4, 1, (gps as u32).to_le_bytes()));
+        tiff.extend(0u32.to_le_bytes());
+        tiff.extend(2u16.to_le_bytes());
+        tiff.extend(entry(0x0001, 2, 2, *b"N\0\0\0"));
+        tiff.extend(entry(0x0002, 5, 3, (rationals as u32).to_le_bytes()));
+        tiff.extend(0u32.to_le_bytes());
+        tiff.extend(latitude());
+        tiff
+    }
+
+    /// Tags in the first IFD of EXIF data written in big endian, as done when stripping.
+    fn ifd_tags(bytes: &[u8]) -> Vec<u16> {
+        let tiff = &bytes[bytes.windows(4).position(|w| w == b"MM\0*").unwrap()..];
+        let ifd = u32::from_be_bytes(tiff[4..8].try_into().unwrap()) as usize;
+        let entries = u16::from_be_bytes([tiff[ifd], tiff[ifd + 1]]) as usize;
+        (0..entries).map(|i| ifd + 2 + i * 12).map(|entry| u16::from_be_bytes([tiff[entry], tiff[entry + 1]])).collect()
+    }
+
+    fn orientation(bytes: &[u8]) -> Orientation {
+        let mut decoder = ImageReader::new(Cursor::new(bytes)).with_guessed_format().unwrap().into_decoder().unwrap();
+        let orientation = decoder.orientation().unwrap();
+        DynamicImage::from_decoder(decoder).unwrap();
+        orientation
+    }
+
+    fn contains(bytes: &[u8], text: &[u8]) -> bool {
+        bytes.windows(text.len()).any(|w| w == text)
+    }
+
+    #[test]
+    fn test_strip_metadata() {
+        let image = DynamicImage::ImageRgb8(image::RgbImage::new(8, 8));
+
+        let mut jpeg = vec![];
+        image.write_to(&mut Cursor::new(&mut jpeg), image::ImageFormat::Jpeg).unwrap();
+        let exif_segment = [b"Exif\0\0".as_slice(), &exif(6)].concat();
+        let mut segments = vec![0xFF, 0xE1];
+        segments.extend((exif_segment.len() as u16 + 2).to_be_bytes());
+        segments.extend(exif_segment);
+        segments.extend([0xFF, 0xFE, 0, 13]);
+        segments.extend(b"serial 1234");
+        jpeg.splice(2..2, segments);
+        jpeg.extend(b"appended image");
+        assert!(contains(&jpeg, &latitude()));
+        let stripped = strip_metadata(&jpeg, Format::Jpeg).unwrap();
+        assert!(!contains(&stripped, b"serial") && !contains(&stripped, b"appended"));
+        assert!(!contains(&stripped, &latitude()));
+        assert_eq!(ifd_tags(&stripped), [ORIENTATION]);
+        assert_eq!(orientation(&stripped), Orientation::Rotate90);
+
+        let mut png = vec![];
+        image.write_to(&mut Cursor::new(&mut png), image::ImageFormat::Png).unwrap();
+        let chunks = [png_chunk(b"eXIf", &exif(3)), png_chunk(b"tEXt", b"GPS\0somewhere")].concat();
+        // after IHDR
+        png.splice(33..33, chunks);
+        assert_eq!(orientation(&png), Orientation::Rotate180);
+        let stripped = strip_metadata(&png, Format::Png).unwrap();
+        assert!(!contains(&stripped, b"somewhere") && !contains(&stripped, &latitude()));
+        assert_eq!(ifd_tags(&stripped), [ORIENTATION]);
+        assert_eq!(orientation(&stripped), Orientation::Rotate180);
+
+        let vp8 = webp::Encoder::from_image(&image).unwrap().encode(80.0).to_vec();
+        let mut header = vec![0x08 | 0x04, 0, 0, 0];
+        header.extend(&7u32.to_le_bytes()[..3]);
+        header.extend(&7u32.to_le_bytes()[..3]);
+        let body = [webp_chunk(b"VP8X", &header), vp8[12..].to_vec(), webp_chunk(b"XMP ", b"<gps/>"), webp_chunk(b"EXIF", &exif(8))].concat();
+        let webp = [b"RIFF".as_slice(), &(body.len() as u32 + 4).to_le_bytes(), b"WEBP", &body].concat();
+        let stripped = strip_metadata(&webp, Format::Webp).unwrap();
+        assert!(!contains(&stripped, b"<gps/>") && !contains(&stripped, &latitude()));
+        assert_eq!(ifd_tags(&stripped), [ORIENTATION]);
+        assert_eq!(stripped[20] & 0x04, 0);
+        assert_eq!(orientation(&stripped), Orientation::Rotate270);
+    }
+
+    #[test]
+    fn test_strip_dir() {
+        let dir = TempDir::new("metadata");
+        fs::create_dir_all(dir.join("photos")).unwrap();
+        let corrupt = [[0xFF, 0xD8, 0xFF, 0xE1, 0x00, 0x20].as_slice(), b"Exif\0\0GPS somewhere"].concat();
+        fs::write(dir.join("photos").join("corrupt.jpg"), corrupt).unwrap();
+        fs::write(dir.join("notes.txt"), "not an image").unwrap();
+
+        let failed = strip_dir(&dir).unwrap();
+        assert_eq!(failed.len(), 1);
+        assert!(failed[0].contains("corrupt.jpg"));
+        // the original is never left with its metadata
+        assert!(!dir.join("photos").join("corrupt.jpg").exists());
+        assert!(dir.join("notes.txt").is_file());
+    }
+}
@@ -0,0 +1,69 @@
+use std::{error::Error, fs, io, path::Path};
+
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+/// A manifest persisted between builds, whose schema is versioned.
+pub trait Versioned: DeserializeOwned + Default {
+    /// Version of the schema written by this build.
+    const VERSION: u32;
+    /// Migrate json of schema `version` to `version + 1`.
+    fn migrate(version: u32, value: Value) -> Result<Value, Box<dyn Error>>;
+}
+
+fn invalid_data(message: String) -> Box<dyn Error> {
+    Box::new(io::Error::new(io::ErrorKind::InvalidData, message))
+}
+
+/// Load a manifest, migrating it from older versions. Returns default if file does not exist.
+/// Manifests without version are of version 0.
+pub fn load<T: Versioned>(path: impl AsRef<Path>) -> Result<T, Box<dyn Error>> {
+    if !fs::exists(&path)? {
+        return Ok(T::default())
+    }
+    let mut value: Value = serde_json::from_reader(io::BufReader::new(fs::File::open(path)?))?;
+    let mut version = match value.get("version") {
+        None => 0,
+        Some(version) => version.as_u64()
+            .and_then(|version| u32::try_from(version).ok())
+            .ok_or_else(|| invalid_data(format!("invalid manifest version {version}")))?,
+    };
+    if version > T::VERSION {
+        return Err(invalid_data(format!("manifest version {version} is newer than supported version {}", T::VERSION)))
+    }
+    while version < T::VERSION {
+        value = T::migrate(version, value)
+            .map_err(|e| invalid_data(format!("cannot migrate manifest from version {version}: {e}")))?;
+        version += 1;
+    }
+    Ok(serde_json::from_value(value)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+    use crate::blog::manifest::BlogManifest;
+    use crate::site::manifest::SiteManifest;
+
+    #[test]
+    fn test_load_unversioned() {
+        let dir = TempDir::new("migration");
+
+        let blog_manifest_path = dir.join("blog-manifest.json");
+        fs::write(&blog_manifest_path, r#"{"blogs":{"a.md":{"password":false,"hint":null,"title":"A","language":"en-US","license":"CC BY 4.0","tags":["rust"],"created":"2024-01-01T00:00:00Z","modified":"2024-01-01T00:00:00Z","preview":null}},"tags":{"rust":["a.md"]}}"#).unwrap();
+        let blog_manifest: BlogManifest = load(&blog_manifest_path).unwrap();
+        assert_eq!(blog_manifest.version, BlogManifest::VERSION);
+        assert_eq!(blog_manifest.blogs["a.md"].title, "A");
+
+        let site_manifest_path = dir.join("site-manifest.json");
+        fs::write(&site_manifest_path, r#"{"demo":{"name":"Demo","introduction":"A demo."}}"#).unwrap();
+        let site_manifest: SiteManifest = load(&site_manifest_path).unwrap();
+        assert_eq!(site_manifest.version, SiteManifest::VERSION);
+        assert!(site_manifest.sites.contains_key("demo"));
+
+        fs::write(&site_manifest_path, r#"{"version":100,"sites":{}}"#).unwrap();
+        assert!(load::<SiteManifest>(&site_manifest_path).is_err());
+        assert!(load::<SiteManifest>(dir.join("absent.json")).unwrap().sites.is_empty());
+    }
+}
@@ -1,20 +1,441 @@
 use handlebars::Handlebars;
-use quick_xml::events::{Event, BytesText};
+use quick_xml::events::{Event, BytesEnd, BytesStart, BytesText};
 use quick_xml::Writer;
 use time::format_description::well_known::Rfc3339;
+use time::Month;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fs;
 use std::io::Cursor;
 use katex::Opts;
 
 use crate::blog::license::License;
-use crate::blog::Blog;
+use crate::blog::tag::tag_slug;
+use crate::blog::manifest::BlogManifest;
+use crate::blog::{Blog, PrivateMetadata};
+use crate::blocks;
+use crate::images::ResponsiveImage;
 
 
-fn render_katex(x: &str, display: bool) -> String {
-    katex::render_with_opts(x, Opts::builder().display_mode(display).build().unwrap()).unwrap_or_else(|_| x.to_string())
+fn render_katex(x: &str, display: bool) -> katex::Result<String> {
+    katex::render_with_opts(x, Opts::builder().display_mode(display).build().unwrap())
 }
-/// Render to html, also returns preview
-pub fn render(md: &str) -> (String, Option<String>) {
+
+/// Unescaped value of an attribute.
+pub fn attribute(e: &BytesStart, name: &[u8]) -> Option<String> {
+    e.attributes().flatten()
+        .find(|attr| attr.key.0 == name)
+        .and_then(|attr| attr.unescape_value().ok().map(|value| value.into_owned()))
+}
+
+/// Rendered html of a blog, with what is found while rendering.
+#[derive(Debug, Default)]
+pub struct Rendered {
+    pub html: String,
+    /// The first paragraph.
+    pub preview: Option<String>,
+    /// Urls of all links.
+    pub links: Vec<String>,
+    /// Urls of all images.
+    pub images: Vec<String>,
+    /// Ids of headings in order, then of figures and footnotes.
+    pub headings: Vec<String>,
+    /// Problems which do not stop rendering, such as invalid math.
+    pub diagnostics: Vec<String>,
+    /// Sections to be encrypted on their own, left as placeholders in `html`.
+    pub secrets: Vec<Secret>,
+}
+
+/// Section between `:::secret{hint="…" password_ref="…"}` and `:::`, encrypted apart from the
+/// public rest of the blog. Assets it refers to are not encrypted unless the blog has password.
+#[derive(Debug, Default, PartialEq)]
+pub struct Secret {
+    pub hint: Option<String>,
+    /// Name of the password like `password_ref` of frontmatter.
+    pub password_ref: Option<String>,
+    pub password: Option<String>,
+    /// Ids of headings, figures and footnotes in the section.
+    pub headings: Vec<String>,
+    pub html: String,
+}
+
+/// Placeholder of the secret section at `index`, replaced by `fill_secrets`.
+fn secret_placeholder(index: usize) -> String {
+    format!(r#"<div class="secret" data-secret="{index}"></div>"#)
+}
+
+/// Attributes in `{key="value" …}` of a directive.
+type Attributes = BTreeMap<String, String>;
+
+fn directive_attributes(s: &str) -> Result<Attributes, String> {
+    let mut attributes = BTreeMap::new();
+    let mut rest = s.trim();
+    while !rest.is_empty() {
+        let (key, value) = rest.split_once('=').ok_or_else(|| format!("expected key=\"value\" in {{{s}}}"))?;
+        let value = value.strip_prefix('"').ok_or_else(|| format!("value of {} shall be quoted", key.trim()))?;
+        let (value, remaining) = value.split_once('"').ok_or_else(|| format!("unclosed value of {}", key.trim()))?;
+        attributes.insert(key.trim().to_string(), value.to_string());
+        rest = remaining.trim_start();
+    }
+    Ok(attributes)
+}
+
+/// Cut secret sections out of markdown, leaving placeholders of them. `:::` in code blocks is ignored.
+fn extract_secrets(md: &str, diagnostics: &mut Vec<String>) -> (String, Vec<(Attributes, String)>) {
+    let mut public = String::new();
+    let mut secrets = vec![];
+    let mut current: Option<(Attributes, String)> = None;
+    let mut fence: Option<String> = None;
+    for line in md.split_inclusive('\n') {
+        let trimmed = line.trim();
+        let marker: String = trimmed.chars().take_while(|&c| c == '`' || c == '~').collect();
+        if let Some(opening) = &fence {
+            if marker.starts_with(opening.as_str()) && trimmed.len() == marker.len() {
+                fence = None;
+            }
+        } else if marker.len() >= 3 && marker.chars().all(|c| c == marker.chars().next().unwrap()) {
+            fence = Some(marker);
+        } else if let Some(directive) = trimmed.strip_prefix(":::secret") && current.is_none() {
+            let attributes = directive.strip_prefix('{').and_then(|d| d.strip_suffix('}'))
+                .map_or_else(|| match directive {
+                    "" => Ok(BTreeMap::new()),
+                    _ => Err(format!("invalid secret section {trimmed}")),
+                }, directive_attributes)
+                .unwrap_or_else(|e| {
+                    diagnostics.push(e);
+                    BTreeMap::new()
+                });
+            current = Some((attributes, String::new()));
+            continue
+        } else if trimmed == ":::" && let Some(secret) = current.take() {
+            public.push_str(&format!("\n{}\n\n", secret_placeholder(secrets.len())));
+            secrets.push(secret);
+            continue
+        }
+        match &mut current {
+            Some((_, content)) => content.push_str(line),
+            None => public.push_str(line),
+        }
+    }
+    if let Some(secret) = current {
+        diagnostics.push("secret section is not closed with :::".to_string());
+        public.push_str(&format!("\n{}\n", secret_placeholder(secrets.len())));
+        secrets.push(secret);
+    }
+    (public, secrets)
+}
+
+/// Replace placeholders of secret sections by their html, in the order of `Rendered::secrets`.
+pub fn fill_secrets(html: &str, sections: &[String]) -> String {
+    sections.iter().enumerate()
+        .fold(html.to_string(), |html, (index, section)| html.replace(&secret_placeholder(index), section))
+}
+
+/// Encrypted secret section with a form to unlock it, which offers to remember the password if
+/// there are `key_ids`.
+pub fn save_secret_section(hint: Option<&str>, ciphertext: String, key_ids: &[String]) -> String {
+    let template_str = fs::read_to_string("template/secret-section.html").unwrap();
+    Handlebars::new().render_template(&template_str, &serde_json::json!({
+        "hint": hint.map(|h| format!("Hint: {h}")).unwrap_or_default(),
+        "content": ciphertext,
+        "key_ids": key_ids.join(" "),
+    })).unwrap()
+}
+
+/// GitHub style anchor of a heading, made unique among `used`.
+pub fn heading_slug(text: &str, used: &[String]) -> String {
+    let slug: String = text.trim().to_lowercase().chars()
+        .filter_map(|c| match c {
+            ' ' => Some('-'),
+            c if c.is_alphanumeric() || c == '-' || c == '_' => Some(c),
+            _ => None,
+        })
+        .collect();
+    let mut unique = slug.clone();
+    let mut count = 0;
+    while used.contains(&unique) {
+        count += 1;
+        unique = format!("{slug}-{count}");
+    }
+    unique
+}
+
+/// Give headings without id an anchor from their text.
+fn add_heading_ids(html: &str) -> (String, Vec<String>) {
+    let mut reader = quick_xml::reader::Reader::from_str(html);
+    let mut writer = Writer::new(Cursor::new(Vec::new()));
+    let mut headings = vec![];
+    // start tag, inner events and text of the heading being read
+    let mut heading: Option<(BytesStart<'static>, Vec<Event<'static>>, String)> = None;
+    loop {
+        match reader.read_event() {
+            Ok(Event::Start(e)) if heading.is_none() && matches!(e.name().0, b"h1" | b"h2" | b"h3" | b"h4" | b"h5" | b"h6") => {
+                heading = Some((e.into_owned(), vec![], String::new()));
+            }
+            Ok(Event::End(e)) if heading.as_ref().is_some_and(|(start, _, _)| start.name() == e.name()) => {
+                let (mut start, events, text) = heading.take().unwrap();
+                let id = match attribute(&start, b"id") {
+                    Some(id) => id,
+                    None => {
+                        let id = heading_slug(&text, &headings);
+                        start.push_attribute(("id", id.as_str()));
+                        id
+                    }
+                };
+                headings.push(id);
+                writer.write_event(Event::Start(start)).unwrap();
+                for event in events {
+                    writer.write_event(event).unwrap();
+                }
+                writer.write_event(Event::End(e)).unwrap();
+            }
+            Ok(Event::Eof) => break,
+            Ok(e) => match &mut heading {
+                Some((_, events, text)) => {
+                    if let Event::Text(t) = &e {
+                        text.push_str(&t.unescape().unwrap_or_default());
+                    }
+                    events.push(e.into_owned());
+                }
+                None => writer.write_event(e).unwrap(),
+            },
+            Err(_) => panic!("Fail to parse html"),
+        }
+    }
+    (String::from_utf8(writer.into_inner().into_inner()).unwrap(), headings)
+}
+
+/// Decode `%XX` sequences of an url path.
+pub fn percent_decode(url: &str) -> String {
+    let bytes = url.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            },
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Blog id and anchor of a link to another blog written as `other.md#anchor`.
+fn blog_link(href: &str) -> Option<(String, Option<&str>)> {
+    if href.contains(':') || href.starts_with('/') {
+        return None
+    }
+    let (path, anchor) = match href.split_once('#') {
+        Some((path, anchor)) => (path, Some(anchor)),
+        None => (href, None),
+    };
+    let id = path.strip_prefix("./").unwrap_or(path);
+    id.ends_with(".md").then(|| (percent_decode(id), anchor))
+}
+
+/// Html with links between blogs resolved.
+#[derive(Debug, Default)]
+pub struct ResolvedLinks {
+    pub html: String,
+    /// Ids of other blogs linked.
+    pub references: Vec<String>,
+    /// Dangling links and anchors.
+    pub diagnostics: Vec<String>,
+}
+
+/// Rewrite links to other blogs written as `other.md` or `other.md#heading` to their rendered
+/// pages, and validate them against the manifest and anchors against `headings` of the blog itself.
+/// Anchors of other blogs whose headings are unknown are not validated.
+pub fn resolve_links(html: &str, id: &str, headings: &[String], manifest: &BlogManifest) -> ResolvedLinks {
+    let mut reader = quick_xml::reader::Reader::from_str(html);
+    let mut writer = Writer::new(Cursor::new(Vec::new()));
+    let mut references: Vec<String> = vec![];
+    let mut diagnostics = vec![];
+    let other_headings = |id: &str| manifest.blogs.get(id).and_then(|blog| blog.headings.as_ref());
+    loop {
+        match reader.read_event() {
+            Ok(Event::Start(e)) if e.name().0 == b"a" => {
+                let Some(href) = attribute(&e, b"href") else {
+                    writer.write_event(Event::Start(e)).unwrap();
+                    continue
+                };
+                let mut start = e.clone();
+                if let Some(anchor) = href.strip_prefix('#') {
+                    if !headings.iter().any(|heading| heading == anchor) {
+                        diagnostics.push(format!("anchor {href} is not found"));
+                    }
+                } else if let Some((target, anchor)) = blog_link(&href) {
+                    if !manifest.blogs.contains_key(&target) {
+                        diagnostics.push(format!("dangling link to {href}"));
+                    } else if let Some(anchor) = anchor
+                        && other_headings(&target).is_some_and(|headings| !headings.iter().any(|heading| heading == anchor)) {
+                        diagnostics.push(format!("anchor #{anchor} is not found in {target}"));
+                    }
+                    let url = match anchor {
+                        Some(anchor) => format!("{}#{anchor}", blog_url(&target)),
+                        None => blog_url(&target),
+                    };
+                    start = e.to_owned();
+                    start.clear_attributes();
+                    for attr in e.attributes().flatten() {
+                        if attr.key.0 == b"href" {
+                            start.push_attribute(("href", url.as_str()));
+                        } else {
+                            start.push_attribute(attr);
+                        }
+                    }
+                    if target != id && !references.contains(&target) {
+                        references.push(target);
+                    }
+                }
+                writer.write_event(Event::Start(start)).unwrap();
+            }
+            Ok(Event::Eof) => break,
+            Ok(e) => writer.write_event(e).unwrap(),
+            Err(_) => panic!("Fail to parse html"),
+        }
+    }
+    ResolvedLinks {
+        html: String::from_utf8(writer.into_inner().into_inner()).unwrap(),
+        references,
+        diagnostics,
+    }
+}
+
+/// Width images take in a blog, which is at most 896px wide with 1rem margin on each side.
+const IMAGE_SIZES: &str = "(max-width: 928px) calc(100vw - 2rem), 896px";
+
+/// Rewrite images in `assets` with generated variants to pictures choosing among them, with
+/// intrinsic size given so that loading them does not shift the layout.
+pub fn responsive_images(html: &str, assets: &str, images: &BTreeMap<String, ResponsiveImage>) -> String {
+    let mut reader = quick_xml::reader::Reader::from_str(html);
+    let mut writer = Writer::new(Cursor::new(Vec::new()));
+    let prefix = format!("{assets}/");
+    loop {
+        match reader.read_event() {
+            Ok(Event::Empty(e)) if e.name().0 == b"img" => {
+                let image = attribute(&e, b"src").and_then(|src| {
+                    let path = percent_decode(&src);
+                    let image = images.get(path.strip_prefix(&prefix)?)?;
+                    Some((src, image))
+                });
+                let Some((src, image)) = image else {
+                    writer.write_event(Event::Empty(e)).unwrap();
+                    continue
+                };
+                let srcset = |format, widths: &[u32]| widths.iter()
+                    .map(|&width| format!("{} {width}w", ResponsiveImage::variant_name(&src, width, format)))
+                    .collect::<Vec<_>>();
+                writer.write_event(Event::Start(BytesStart::new("picture"))).unwrap();
+                let mut fallback = vec![];
+                for (format, widths) in &image.variants {
+                    if *format == image.format {
+                        fallback = srcset(*format, widths);
+                        continue
+                    }
+                    let mut source = BytesStart::new("source");
+                    source.push_attribute(("type", format.mime()));
+                    source.push_attribute(("srcset", srcset(*format, widths).join(", ").as_str()));
+                    source.push_attribute(("sizes", IMAGE_SIZES));
+                    writer.write_event(Event::Empty(source)).unwrap();
+                }
+                let mut img = e.to_owned();
+                img.clear_attributes();
+                img.extend_attributes(e.attributes().flatten()
+                    .filter(|attr| !matches!(attr.key.0, b"srcset" | b"sizes" | b"width" | b"height")));
+                if !fallback.is_empty() {
+                    fallback.push(format!("{src} {}w", image.width));
+                    img.push_attribute(("srcset", fallback.join(", ").as_str()));
+                    img.push_attribute(("sizes", IMAGE_SIZES));
+                }
+                img.push_attribute(("width", image.width.to_string().as_str()));
+                img.push_attribute(("height", image.height.to_string().as_str()));
+                writer.write_event(Event::Empty(img)).unwrap();
+                writer.write_event(Event::End(BytesEnd::new("picture"))).unwrap();
+            }
+            Ok(Event::Eof) => break,
+            Ok(e) => writer.write_event(e).unwrap(),
+            Err(_) => panic!("Fail to parse html"),
+        }
+    }
+    String::from_utf8(writer.into_inner().into_inner()).unwrap()
+}
+
+/// Paths of assets which `src` and `href` refer to, relative to the assets directory.
+pub fn asset_paths(html: &str, assets: &str) -> BTreeSet<String> {
+    let mut reader = quick_xml::reader::Reader::from_str(html);
+    let prefix = format!("{assets}/");
+    let mut paths = BTreeSet::new();
+    loop {
+        match reader.read_event() {
+            Ok(Event::Start(e) | Event::Empty(e)) => {
+                paths.extend([b"src".as_slice(), b"href"].into_iter()
+                    .filter_map(|name| attribute(&e, name))
+                    .filter_map(|url| percent_decode(&url).strip_prefix(&prefix).map(str::to_string)));
+            },
+            Ok(Event::Eof) | Err(_) => break,
+            Ok(_) => {},
+        }
+    }
+    paths
+}
+
+/// Point `src` and `href` referring to assets of a blog with password, or of a secret section, to
+/// their encrypted files, as `data-encrypted-src` and `data-encrypted-href` for the decrypt script to
+/// load them.
+pub fn encrypted_assets(html: &str, assets: &str, names: &BTreeMap<String, String>) -> String {
+    let mut reader = quick_xml::reader::Reader::from_str(html);
+    let mut writer = Writer::new(Cursor::new(Vec::new()));
+    let prefix = format!("{assets}/");
+    let rewrite = |e: &BytesStart| {
+        let mut start = e.to_owned();
+        start.clear_attributes();
+        let mut download = None;
+        for attr in e.attributes().flatten() {
+            let encrypted = matches!(attr.key.0, b"src" | b"href")
+                .then(|| attr.unescape_value().ok()).flatten()
+                .and_then(|url| {
+                    let path = percent_decode(&url);
+                    let name = names.get(path.strip_prefix(&prefix)?)?;
+                    download = path.rsplit('/').next().map(str::to_string);
+                    Some(format!("{assets}/{name}"))
+                });
+            match encrypted {
+                Some(url) => {
+                    let key = format!("data-encrypted-{}", String::from_utf8_lossy(attr.key.0));
+                    start.push_attribute((key.as_str(), url.as_str()));
+                },
+                None => start.push_attribute(attr),
+            }
+        }
+        // keep the name of downloaded files, instead of a random one of the blob url
+        if let Some(download) = download
+            && e.name().0 == b"a" && attribute(e, b"download").is_none() {
+            start.push_attribute(("download", download.as_str()));
+        }
+        start.into_owned()
+    };
+    loop {
+        match reader.read_event() {
+            Ok(Event::Start(e)) => writer.write_event(Event::Start(rewrite(&e))).unwrap(),
+            Ok(Event::Empty(e)) => writer.write_event(Event::Empty(rewrite(&e))).unwrap(),
+            Ok(Event::Eof) => break,
+            Ok(e) => writer.write_event(e).unwrap(),
+            Err(_) => panic!("Fail to parse html"),
+        }
+    }
+    String::from_utf8(writer.into_inner().into_inner()).unwrap()
+}
+
+/// Render markdown to html with math, footnote ids are prefixed with `footnote_prefix`.
+fn render_markdown(md: &str, footnote_prefix: &str) -> Rendered {
     let options = markdown::Options {
         parse: markdown::ParseOptions { constructs: markdown::Constructs {
             math_flow: true,
@@ -27,9 +448,12 @@ pub fn render(md: &str) -> (String, Option<String>) {
         compile: markdown::CompileOptions {
             allow_dangerous_html: true,
             allow_dangerous_protocol: true,
+            gfm_footnote_label_attributes: Some(r#"class="footnotes-label""#.to_string()),
+            gfm_footnote_clobber_prefix: Some(footnote_prefix.to_string()),
             ..markdown::CompileOptions::gfm()
         }
     };
+    let mut diagnostics = vec![];
     let rendered = markdown::to_html_with_options(md, &options).unwrap();
     // render math
 
@@ -40,6 +464,8 @@ pub fn render(md: &str) -> (String, Option<String>) {
     let mut math_content = String::new();
     let mut preview = None;
     let mut preview_buffer = None;
+    let mut links = vec![];
+    let mut images = vec![];
 
     let mut reader = reader;
     // Find math blocks and content
@@ -51,14 +477,12 @@ pub fn render(md: &str) -> (String, Option<String>) {
                 if tag == b"code" {
                     let mut is_math = None;
                     for attr in e.attributes().flatten() {
-                        if attr.key.0 == b"class" {
-                            if let Ok(val) = std::str::from_utf8(&attr.value) {
-                                if val.contains("language-math") {
-                                    let display = val.contains("math-display");
-                                    is_math = Some(display);
-                                    break;
-                                }
-                            }
+                        if attr.key.0 == b"class"
+                            && let Ok(val) = std::str::from_utf8(&attr.value)
+                            && val.contains("language-math") {
+                            let display = val.contains("math-display");
+                            is_math = Some(display);
+                            break;
                         }
                     }
                     if let Some(display) = is_math {
@@ -67,6 +491,8 @@ pub fn render(md: &str) -> (String, Option<String>) {
                     }
                 } else if preview_buffer.is_none() && preview.is_none() && tag == b"p" {
                     preview_buffer = Some(String::new())
+                } else if tag == b"a" && let Some(href) = attribute(e, b"href") {
+                    links.push(href);
                 }
                 writer.write_event(Event::Start(e.clone())).unwrap();
             }
@@ -74,15 +500,17 @@ pub fn render(md: &str) -> (String, Option<String>) {
                 let tag = e.name().0;
                 if tag == b"code" {
                     if let Some(display) = math_mode_display {
-                        let rendered = render_katex(&math_content, display);
+                        let rendered = render_katex(&math_content, display).unwrap_or_else(|e| {
+                            diagnostics.push(format!("KaTeX error in `{math_content}`: {e}"));
+                            math_content.clone()
+                        });
                         writer.write_event(Event::Text(BytesText::from_escaped(&rendered))).unwrap();
                         math_mode_display = None;
                         math_content.clear();
                     }
-                } else if tag == b"p" {
-                    if let Some(buf) = preview_buffer.take() {
-                        preview = Some(buf);
-                    }
+                } else if tag == b"p"
+                    && let Some(buf) = preview_buffer.take() {
+                    preview = Some(buf);
                 }
                 writer.write_event(Event::End(e.clone())).unwrap();
             }
@@ -100,6 +528,12 @@ pub fn render(md: &str) -> (String, Option<String>) {
                     writer.write_event(Event::Text(e)).unwrap();
                 }
             }
+            Ok(Event::Empty(ref e)) => {
+                if e.name().0 == b"img" && let Some(src) = attribute(e, b"src") {
+                    images.push(src);
+                }
+                writer.write_event(Event::Empty(e.clone())).unwrap();
+            }
             Ok(Event::Eof) => {
                 writer.write_event(Event::Eof).unwrap();
                 break
@@ -110,11 +544,177 @@ pub fn render(md: &str) -> (String, Option<String>) {
             Err(_) => panic!("Fail to parse html"),
         }
     }
-    let result = writer.into_inner().into_inner();
-    (String::from_utf8(result).unwrap(), preview)
+    Rendered {
+        html: String::from_utf8(writer.into_inner().into_inner()).unwrap(),
+        preview,
+        links,
+        images,
+        diagnostics,
+        ..Default::default()
+    }
+}
+
+/// Take html of secret sections out of their elements, leaving placeholders of them.
+fn take_secrets(html: &str) -> (String, Vec<String>) {
+    let mut reader = quick_xml::reader::Reader::from_str(html);
+    let mut writer = Writer::new(Cursor::new(Vec::new()));
+    let mut sections = vec![];
+    // depth of nested elements and html of the section being read
+    let mut section: Option<(usize, Writer<Cursor<Vec<u8>>>)> = None;
+    loop {
+        match reader.read_event() {
+            Ok(Event::Start(e)) if section.is_none() && e.name().0 == b"div" && attribute(&e, b"data-secret").is_some() => {
+                section = Some((0, Writer::new(Cursor::new(Vec::new()))));
+            }
+            Ok(Event::End(e)) if section.as_ref().is_some_and(|(depth, _)| *depth == 0) && e.name().0 == b"div" => {
+                let (_, inner) = section.take().unwrap();
+                writer.write_event(Event::Text(BytesText::from_escaped(secret_placeholder(sections.len())))).unwrap();
+                sections.push(String::from_utf8(inner.into_inner().into_inner()).unwrap());
+            }
+            Ok(Event::Eof) => break,
+            Ok(e) => match &mut section {
+                Some((depth, inner)) => {
+                    match &e {
+                        Event::Start(_) => *depth += 1,
+                        Event::End(_) => *depth -= 1,
+                        _ => {},
+                    }
+                    inner.write_event(e).unwrap();
+                }
+                None => writer.write_event(e).unwrap(),
+            },
+            Err(_) => panic!("Fail to parse html"),
+        }
+    }
+    (String::from_utf8(writer.into_inner().into_inner()).unwrap(), sections)
+}
+
+/// Ids of all elements in html.
+fn element_ids(html: &str) -> Vec<String> {
+    let mut reader = quick_xml::reader::Reader::from_str(html);
+    let mut ids = vec![];
+    loop {
+        match reader.read_event() {
+            Ok(Event::Start(e) | Event::Empty(e)) => ids.extend(attribute(&e, b"id")),
+            Ok(Event::Eof) | Err(_) => break,
+            Ok(_) => {},
+        }
+    }
+    ids
+}
+
+/// Render to html, also returns preview
+pub fn render(md: &str) -> Rendered {
+    let mut diagnostics = vec![];
+    let (md, sections) = extract_secrets(md, &mut diagnostics);
+    let mut rendered = render_markdown(&md, "user-content-");
+    diagnostics.append(&mut rendered.diagnostics);
+
+    // secret sections are rendered on their own so that nothing of them leaks into the public
+    // footnotes, then put back in their elements until headings and figures are numbered
+    let mut attributes = vec![];
+    let mut contents = vec![];
+    for (index, (section_attributes, content)) in sections.into_iter().enumerate() {
+        let (content, nested) = extract_secrets(&content, &mut diagnostics);
+        if !nested.is_empty() {
+            diagnostics.push("secret sections cannot be nested".to_string());
+        }
+        let prefix = format!("user-content-secret-{index}-");
+        let mut section = render_markdown(&content, &prefix);
+        // the label of footnotes is not prefixed by markdown
+        section.html = section.html.replace(r#""footnote-label""#, &format!(r#""{prefix}footnote-label""#));
+        rendered.links.extend(section.links);
+        rendered.images.extend(section.images);
+        diagnostics.extend(section.diagnostics);
+        diagnostics.extend(section_attributes.keys()
+            .filter(|key| !matches!(key.as_str(), "hint" | "password_ref" | "password"))
+            .map(|key| format!("unknown attribute {key} of secret section")));
+        attributes.push(section_attributes);
+        contents.push(format!(r#"<div class="secret" data-secret="{index}">{}</div>"#, section.html));
+    }
+    let html = fill_secrets(&rendered.html, &contents);
+
+    let (html, mut headings) = add_heading_ids(&html);
+    let (html, figures) = blocks::add_figures(&html, &headings);
+    headings.extend(figures);
+    let html = blocks::add_definition_lists(&blocks::add_admonitions(&html));
+    headings.extend(blocks::footnote_ids(&html));
+
+    let (html, sections) = take_secrets(&html);
+    let secrets: Vec<_> = attributes.into_iter().zip(sections).map(|(mut attributes, html)| Secret {
+        hint: attributes.remove("hint"),
+        password_ref: attributes.remove("password_ref"),
+        password: attributes.remove("password"),
+        headings: element_ids(&html),
+        html,
+    }).collect();
+    // anchors in secret sections are not public
+    headings.retain(|heading| !secrets.iter().any(|secret| secret.headings.contains(heading)));
+    Rendered {
+        html,
+        headings,
+        diagnostics,
+        secrets,
+        ..rendered
+    }
+}
+
+/// Relative url of a rendered blog from its id.
+fn blog_url(id: &str) -> String {
+    format!("{}.html", id.strip_suffix(".md").unwrap_or(id))
+}
+
+/// File name of the static page of a tag.
+pub fn tag_file_name(tag: &str) -> String {
+    format!("{}.html", tag_slug(tag))
+}
+
+/// Display name, color, description and url of a tag, with the url relative to `tags_dir`.
+fn tag_context(manifest: &BlogManifest, tag: &str, tags_dir: &str) -> serde_json::Value {
+    let metadata = manifest.tag_metadata.get(tag);
+    serde_json::json!({
+        "name": metadata.and_then(|metadata| metadata.name.as_deref()).unwrap_or(tag),
+        "color": metadata.and_then(|metadata| metadata.color.as_deref()),
+        "description": metadata.and_then(|metadata| metadata.description.as_deref()),
+        "url": format!("{tags_dir}{}", tag_file_name(tag)),
+    })
+}
+
+/// File name of the static archive page of a year.
+pub fn archive_file_name(year: i32) -> String {
+    format!("{year}.html")
 }
 
-pub fn save_html(metadata: &Blog, content: String, copyright_name: &str) -> String {
+/// Title and url of a linked blog.
+fn link_context(manifest: &BlogManifest, id: &str) -> Option<serde_json::Value> {
+    manifest.blogs.get(id).map(|blog| serde_json::json!({
+        "title": blog.title,
+        "url": blog_url(id),
+    }))
+}
+
+/// Listing and previous / next links of the series the blog belongs to.
+fn series_context(manifest: &BlogManifest, id: &str) -> Option<serde_json::Value> {
+    let name = manifest.blogs.get(id)?.series.as_ref()?;
+    let blogs = manifest.series_blogs(name);
+    let index = blogs.iter().position(|(blog_id, _)| *blog_id == id)?;
+    let link = |(blog_id, blog): &(&str, &Blog)| serde_json::json!({
+        "title": blog.title,
+        "url": blog_url(blog_id),
+        "current": *blog_id == id,
+    });
+    Some(serde_json::json!({
+        "name": name,
+        "part": index + 1,
+        "total": blogs.len(),
+        "prev": index.checked_sub(1).map(|i| link(&blogs[i])),
+        "next": blogs.get(index + 1).map(link),
+        "blogs": blogs.iter().map(link).collect::<Vec<_>>(),
+    }))
+}
+
+pub fn save_html(manifest: &BlogManifest, id: &str, content: String, copyright_name: &str) -> String {
+    let metadata = &manifest.blogs[id];
     let reg = Handlebars::new();
     // read template from file
     let template_str = fs::read_to_string("template/blog.html").unwrap();
@@ -124,7 +724,12 @@ pub fn save_html(metadata: &Blog, content: String, copyright_name: &str) -> Stri
         "date": date_str,
         "lang": metadata.language,
         "content": content,
-        "tags": metadata.tags,
+        "tags": metadata.tags.iter().map(|tag| tag_context(manifest, tag, "tags/")).collect::<Vec<_>>(),
+        "archive_url": format!("archive/{}#{}", archive_file_name(metadata.created.year()), month_id(metadata.created.year(), metadata.created.month())),
+        "series": series_context(manifest, id),
+        "prev": metadata.navigation.prev.as_ref().and_then(|prev| link_context(manifest, prev)),
+        "next": metadata.navigation.next.as_ref().and_then(|next| link_context(manifest, next)),
+        "related": metadata.navigation.related.iter().filter_map(|related| link_context(manifest, related)).collect::<Vec<_>>(),
         "copyright_year": metadata.get_copyright_year(),
         "copyright_name": copyright_name,
         "license": metadata.license.as_ref().map(|license| {
@@ -144,7 +749,28 @@ pub fn save_html(metadata: &Blog, content: String, copyright_name: &str) -> Stri
         })
     })).unwrap()
 }
-pub fn save_html_secret(metadata: &Blog, content: String, copyright_name: &str) -> String {
+/// Real metadata of a blog with private metadata as a JSON script, put before its content so that
+/// the decrypt script restores it. Tags link to their pages only if some public blog has them.
+pub fn private_metadata_html(manifest: &BlogManifest, private: &PrivateMetadata) -> String {
+    let tags: Vec<_> = private.tags.iter().map(|tag| {
+        let mut context = tag_context(manifest, tag, "tags/");
+        if !manifest.tags.contains_key(tag) {
+            context["url"] = serde_json::Value::Null;
+        }
+        context
+    }).collect();
+    let json = serde_json::json!({
+        "title": private.title,
+        "lang": private.language,
+        "tags": tags,
+    });
+    // `</script>` in strings would end the script
+    format!(r#"<script type="application/json" id="private-metadata">{}</script>"#, json.to_string().replace("</", "<\\/"))
+}
+
+/// Page of a blog with password, which offers to remember the password if there are `key_ids`.
+pub fn save_html_secret(manifest: &BlogManifest, id: &str, content: String, key_ids: &[String], copyright_name: &str) -> String {
+    let metadata = &manifest.blogs[id];
 
     let reg = Handlebars::new();
     // read template from file
@@ -154,8 +780,201 @@ pub fn save_html_secret(metadata: &Blog, content: String, copyright_name: &str)
             let mut ans = "Hint: ".to_string();
             ans.push_str(h);
             ans
-        }).unwrap_or(String::new()),
+        }).unwrap_or_default(),
         "content": content,
+        "key_ids": key_ids.join(" "),
     })).unwrap();
-    save_html(metadata, secret_content, copyright_name)
-}
\ No newline at end of file
+    save_html(manifest, id, secret_content, copyright_name)
+}
+
+/// Base64 ciphertexts in a written blog, of the blog with password and of its secret sections.
+pub fn ciphertexts(html: &str) -> Vec<String> {
+    let mut ciphertexts = vec![];
+    for start in [r#"<code id="ciphertext""#, r#"<code class="secret-ciphertext""#] {
+        for (index, _) in html.match_indices(start) {
+            let rest = &html[index + start.len()..];
+            let Some(rest) = rest.split_once('>').map(|(_, rest)| rest) else { continue };
+            let Some(end) = rest.find("</code>") else { continue };
+            // templates escape `/` and `=` of base64
+            if let Ok(ciphertext) = quick_xml::escape::unescape(&rest[..end]) {
+                ciphertexts.push(ciphertext.trim().to_string());
+            }
+        }
+    }
+    ciphertexts
+}
+
+/// Anchor of a month in a yearly archive page.
+fn month_id(year: i32, month: Month) -> String {
+    format!("{year}-{:02}", month as u8)
+}
+
+/// Registry with partials shared by static listing pages.
+fn listing_registry() -> Handlebars<'static> {
+    let mut reg = Handlebars::new();
+    reg.register_partial("listing-head", fs::read_to_string("template/listing-head.html").unwrap()).unwrap();
+    reg.register_partial("listing-blogs", fs::read_to_string("template/listing-blogs.html").unwrap()).unwrap();
+    reg
+}
+
+/// A blog in static listing pages, which are placed in a subdirectory of blogs.
+fn listing_blog_context(manifest: &BlogManifest, id: &str, blog: &Blog) -> serde_json::Value {
+    serde_json::json!({
+        "title": blog.title,
+        "url": format!("../{}", blog_url(id)),
+        "date": blog.created.format(&Rfc3339).unwrap(),
+        "day": blog.created.date().to_string(),
+        "password": blog.password,
+        "tags": blog.tags.iter().map(|tag| tag_context(manifest, tag, "../tags/")).collect::<Vec<_>>(),
+    })
+}
+
+/// Blogs with given ids, newest first.
+fn listing_blogs<'a>(manifest: &'a BlogManifest, ids: impl IntoIterator<Item = &'a String>) -> Vec<(&'a str, &'a Blog)> {
+    let mut blogs: Vec<_> = ids.into_iter()
+        .filter_map(|id| manifest.blogs.get_key_value(id))
+        .map(|(id, blog)| (id.as_str(), blog))
+        .collect();
+    blogs.sort_by(|(a_id, a), (b_id, b)| b.created.cmp(&a.created).then(a_id.cmp(b_id)));
+    blogs
+}
+
+/// Url of the archive page of the latest year, relative to listing pages.
+fn latest_archive_url(manifest: &BlogManifest) -> Option<String> {
+    manifest.blogs.values()
+        .map(|blog| blog.created.year())
+        .max()
+        .map(|year| format!("../archive/{}", archive_file_name(year)))
+}
+
+/// Render the tag index page and the page of every tag, as file names and contents.
+pub fn save_tags_html(manifest: &BlogManifest) -> Vec<(String, String)> {
+    let reg = listing_registry();
+    let tag_template = fs::read_to_string("template/tag.html").unwrap();
+    let index_template = fs::read_to_string("template/tags.html").unwrap();
+    let archive_url = latest_archive_url(manifest);
+
+    let mut tags: Vec<_> = manifest.tags.iter().filter(|(_, ids)| !ids.is_empty()).collect();
+    tags.sort_by_key(|(tag, _)| *tag);
+
+    let mut pages: Vec<_> = tags.iter().map(|(tag, ids)| {
+        let blogs = listing_blogs(manifest, *ids);
+        (tag_file_name(tag), reg.render_template(&tag_template, &serde_json::json!({
+            "title": tag_context(manifest, tag, "")["name"],
+            "tag": tag_context(manifest, tag, ""),
+            "archive_url": archive_url,
+            "blogs": blogs.iter().map(|(id, blog)| listing_blog_context(manifest, id, blog)).collect::<Vec<_>>(),
+        })).unwrap())
+    }).collect();
+
+    pages.push(("index.html".to_string(), reg.render_template(&index_template, &serde_json::json!({
+        "title": "Tags",
+        "archive_url": archive_url,
+        "tags": tags.iter().map(|(tag, ids)| {
+            let mut context = tag_context(manifest, tag, "");
+            context["count"] = ids.len().into();
+            context
+        }).collect::<Vec<_>>(),
+    })).unwrap()));
+    pages
+}
+
+/// Render the archive page of every year with blogs grouped by month, as file names and contents.
+pub fn save_archive_html(manifest: &BlogManifest) -> Vec<(String, String)> {
+    let reg = listing_registry();
+    let template = fs::read_to_string("template/archive.html").unwrap();
+    let blogs = listing_blogs(manifest, manifest.blogs.keys());
+
+    let mut years: Vec<_> = blogs.iter().map(|(_, blog)| blog.created.year()).collect();
+    years.dedup();
+
+    years.iter().map(|&year| {
+        let mut months: Vec<(Month, Vec<serde_json::Value>)> = vec![];
+        for (id, blog) in blogs.iter().filter(|(_, blog)| blog.created.year() == year) {
+            let month = blog.created.month();
+            match months.last_mut() {
+                Some((last, month_blogs)) if *last == month => month_blogs.push(listing_blog_context(manifest, id, blog)),
+                _ => months.push((month, vec![listing_blog_context(manifest, id, blog)])),
+            }
+        }
+        (archive_file_name(year), reg.render_template(&template, &serde_json::json!({
+            "title": format!("Archive of {year}"),
+            "tags_url": "../tags/index.html",
+            "years": years.iter().map(|&other| serde_json::json!({
+                "year": other,
+                "url": archive_file_name(other),
+                "current": other == year,
+            })).collect::<Vec<_>>(),
+            "months": months.into_iter().map(|(month, month_blogs)| serde_json::json!({
+                "id": month_id(year, month),
+                "name": month.to_string(),
+                "blogs": month_blogs,
+            })).collect::<Vec<_>>(),
+        })).unwrap())
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_links() {
+        let rendered = render("## Setup\n\n[other](other%20post.md#usage) [missing](missing.md) [self](#setup) [bad](#nope)\n\n## Setup\n");
+        assert_eq!(rendered.headings, ["setup", "setup-1"]);
+
+        let mut manifest = BlogManifest::default();
+        let mut other: Blog = serde_json::from_value(serde_json::json!({
+            "password": false, "hint": null, "title": "Other", "license": null,
+            "created": "2024-01-01T00:00:00Z", "modified": "2024-01-01T00:00:00Z", "preview": null,
+        })).unwrap();
+        other.headings = Some(vec!["install".to_string()]);
+        manifest.blogs.insert("other post.md".to_string(), other);
+
+        let resolved = resolve_links(&rendered.html, "this.md", &rendered.headings, &manifest);
+        assert!(resolved.html.contains(r##"href="other post.html#usage""##));
+        assert!(resolved.html.contains(r#"href="missing.html""#));
+        assert_eq!(resolved.references, ["other post.md", "missing.md"]);
+        assert_eq!(resolved.diagnostics, [
+            "anchor #usage is not found in other post.md",
+            "dangling link to missing.md",
+            "anchor #nope is not found",
+        ]);
+    }
+
+    #[test]
+    fn test_secrets() {
+        let rendered = render("Public.\n\n:::secret{hint=\"Exam\" password_ref=\"class\"}\n## Answers\n\n```\n:::\n```\n[key](https://example.com)\n:::\n\nAfter.\n");
+        assert_eq!(rendered.preview.as_deref(), Some("Public."));
+        assert_eq!(rendered.headings, Vec::<String>::new());
+        assert_eq!(rendered.links, ["https://example.com"]);
+        assert_eq!(rendered.secrets, [Secret {
+            hint: Some("Exam".to_string()),
+            password_ref: Some("class".to_string()),
+            password: None,
+            headings: vec!["answers".to_string()],
+            html: "<h2 id=\"answers\">Answers</h2>\n<pre><code>:::\n</code></pre>\n<p><a href=\"https://example.com\">key</a></p>\n".to_string(),
+        }]);
+        assert!(!rendered.html.contains("Answers"));
+
+        let html = fill_secrets(&rendered.html, &["<p>filled</p>".to_string()]);
+        assert_eq!(html, "<p>Public.</p>\n<p>filled</p>\n<p>After.</p>\n");
+        let section = save_secret_section(Some("Exam"), "AQ/b+c==".to_string(), &["00ff".to_string()]);
+        assert_eq!(ciphertexts(&section), ["AQ/b+c=="]);
+
+        // anchors and figures are numbered along with the public rest
+        let rendered = render("# Answers\n\n![](a.png)\n\n:::secret{password_ref=\"class\"}\n# Answers\n\n![](b.png)\n\nSee[^a].\n\n[^a]: Hidden.\n:::\n\n![](c.png)\n");
+        assert_eq!(rendered.headings, ["answers", "figure-1", "figure-3"]);
+        assert_eq!(rendered.secrets[0].headings, ["answers-1", "figure-2", "user-content-secret-0-fnref-a", "user-content-secret-0-footnote-label", "user-content-secret-0-fn-a"]);
+        assert!(rendered.secrets[0].html.contains("Figure 2</span>"));
+        assert!(rendered.html.contains("Figure 3</span>") && !rendered.html.contains("Hidden"));
+
+        // assets only used in sections are told apart to be encrypted with them
+        let rendered = render("![](post.assets/a.png)\n\n:::secret{password_ref=\"class\"}\n![](post.assets/a.png) [answers](post.assets/my%20answers.pdf)\n:::\n");
+        assert_eq!(asset_paths(&rendered.html, "post.assets"), BTreeSet::from(["a.png".to_string()]));
+        assert_eq!(asset_paths(&rendered.secrets[0].html, "post.assets"), BTreeSet::from(["a.png".to_string(), "my answers.pdf".to_string()]));
+
+        let rendered = render(":::secret{hint=Exam}\nunclosed\n");
+        assert_eq!(rendered.diagnostics, ["value of hint shall be quoted", "secret section is not closed with :::"]);
+    }
+}
@@ -0,0 +1,32 @@
+use schemars::generate::SchemaSettings;
+use schemars::transform::RecursiveTransform;
+use schemars::{JsonSchema, Schema};
+use serde_json::Value;
+
+/// TOML has no null, optional keys are only omitted.
+fn remove_null(schema: &mut Schema) {
+    if let Some(Value::Array(types)) = schema.get_mut("type") {
+        types.retain(|ty| ty != "null");
+        if types.len() == 1 {
+            let ty = types.remove(0);
+            schema.insert("type".to_string(), ty);
+        }
+    }
+    if let Some(Value::Array(any_of)) = schema.get_mut("anyOf") {
+        any_of.retain(|subschema| subschema.get("type").is_none_or(|ty| ty != "null"));
+        if any_of.len() == 1
+            && let Some(Value::Object(subschema)) = schema.remove("anyOf").and_then(|mut any_of| any_of.get_mut(0).map(Value::take)) {
+            for (key, value) in subschema {
+                schema.insert(key, value);
+            }
+        }
+    }
+}
+
+/// JSON Schema of a type deserialized from TOML.
+pub fn toml_schema<T: JsonSchema>() -> Schema {
+    SchemaSettings::draft2020_12()
+        .with_transform(RecursiveTransform(remove_null))
+        .into_generator()
+        .into_root_schema_for::<T>()
+}
@@ -1,10 +1,35 @@
-use std::collections::HashMap;
+use std::collections::BTreeMap;
+use std::error::Error;
 
 use serde::{Serialize, Deserialize};
+use serde_json::Value;
+use ts_rs::TS;
 
+use crate::migration::Versioned;
 use super::Site;
 
 
 
-#[derive(Debug, Default, Serialize, Deserialize)]
-pub struct SiteManifest(pub HashMap<String, Site>);
+#[derive(Debug, Serialize, Deserialize, TS)]
+pub struct SiteManifest {
+    pub version: u32,
+    #[ts(type = "Record<string, Site>")]
+    pub sites: BTreeMap<String, Site>,
+}
+
+impl Default for SiteManifest {
+    fn default() -> Self {
+        Self { version: Self::VERSION, sites: BTreeMap::new() }
+    }
+}
+
+impl Versioned for SiteManifest {
+    const VERSION: u32 = 1;
+    fn migrate(version: u32, value: Value) -> Result<Value, Box<dyn Error>> {
+        match version {
+            // sites were the manifest itself.
+            0 => Ok(serde_json::json!({ "version": 1, "sites": value })),
+            _ => Err("unknown version".into()),
+        }
+    }
+}
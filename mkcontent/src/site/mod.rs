@@ -1,8 +1,12 @@
 use serde::{Serialize, Deserialize};
+use schemars::JsonSchema;
+use ts_rs::TS;
 
 pub mod manifest;
 
-#[derive(Debug, Serialize, Deserialize)]
+/// Declared by `site.toml` in the directory of a site.
+#[derive(Debug, Serialize, Deserialize, TS, JsonSchema)]
+#[schemars(title = "Site")]
 pub struct Site {
     name: String,
     introduction: String,
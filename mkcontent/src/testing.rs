@@ -0,0 +1,35 @@
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::fs;
+
+/// Empty directory of a test in the temporary directory, removed when dropped, even if the test fails.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    /// `name` tells apart tests running at the same time.
+    pub fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("mkcontent-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TempDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
@@ -0,0 +1,35 @@
+use ts_rs::TS;
+
+use crate::blog::license::License;
+use crate::blog::manifest::{BlogManifest, Navigation};
+use crate::blog::shard::{BlogPage, ListedBlog, ListingIndex, ShardIndex};
+use crate::blog::tag::TagMetadata;
+use crate::blog::{Blog, Language};
+use crate::site::manifest::SiteManifest;
+use crate::site::Site;
+
+/// TypeScript declarations of manifests and API files, exported for the frontend.
+pub fn typescript() -> String {
+    let decls = [
+        BlogManifest::decl(),
+        Blog::decl(),
+        Language::decl(),
+        License::decl(),
+        Navigation::decl(),
+        TagMetadata::decl(),
+        ShardIndex::decl(),
+        ListingIndex::decl(),
+        BlogPage::decl(),
+        ListedBlog::decl(),
+        SiteManifest::decl(),
+        Site::decl(),
+    ];
+    let mut ts = String::from("// Generated by `mkcontent gen-types` from the Rust definitions. Do not edit.\n");
+    for decl in decls {
+        ts.push('\n');
+        ts.push_str("export ");
+        ts.push_str(&decl);
+        ts.push('\n');
+    }
+    ts
+}
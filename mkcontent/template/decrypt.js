@@ -1,29 +1,11 @@
 /**
- * Decodes a base64-encoded ciphertext and decrypts it using the provided password.
- * The base64 data format: [16 bytes salt][16 bytes iv][ciphertext]
- * Key is derived using PBKDF2 (SHA-256), AES-256-CBC, PKCS7 padding.
+ * Derives the key and IV wrapping the content key from the password and salt using PBKDF2 (SHA-256).
  *
- * @param {string} base64Ciphertext - The base64-encoded ciphertext.
  * @param {string} password - The password for decryption.
- * @returns {Promise<string>} The decrypted plaintext.
+ * @param {Uint8Array} salt - The salt the key is derived with.
+ * @returns {Promise<Uint8Array>} 32 bytes of the key for AES-256-CBC, then 16 bytes of the IV.
  */
-async function decryptBase64Ciphertext(base64Ciphertext, password) {
-    function base64ToArrayBuffer(base64) {
-        const binary = atob(base64);
-        const len = binary.length;
-        const bytes = new Uint8Array(len);
-        for (let i = 0; i < len; i++) bytes[i] = binary.charCodeAt(i);
-        return bytes.buffer;
-    }
-    
-    const data = new Uint8Array(base64ToArrayBuffer(base64Ciphertext));
-    if (data.length < 32) throw new Error('Invalid ciphertext: too short');
-
-    const salt = data.slice(0, 16);
-    const iv = data.slice(16, 32);
-    const ciphertext = data.slice(32);
-
-    // Derive key using PBKDF2 (SHA-256)
+async function deriveBits(password, salt) {
     const enc = new TextEncoder();
     if (window.crypto.subtle === undefined) {
         throw new Error("Unable to decrypt in a HTTP environment")
@@ -33,9 +15,9 @@ async function decryptBase64Ciphertext(base64Ciphertext, password) {
         enc.encode(password),
         { name: 'PBKDF2' },
         false,
-        ['deriveKey']
+        ['deriveBits']
     );
-    const key = await window.crypto.subtle.deriveKey(
+    return new Uint8Array(await window.crypto.subtle.deriveBits(
         {
             name: 'PBKDF2',
             salt: salt,
@@ -43,17 +25,21 @@ async function decryptBase64Ciphertext(base64Ciphertext, password) {
             hash: 'SHA-256'
         },
         keyMaterial,
-        { name: 'AES-CBC', length: 256 },
-        false,
-        ['decrypt']
-    );
-    console.log(key);
+        384
+    ));
+}
 
-    // Decrypt using AES-256-CBC
-    let decrypted;
+/**
+ * Decrypts using AES-256-CBC, PKCS7 padding is removed by the browser.
+ *
+ * @param {CryptoKey} key - The derived key.
+ * @param {Uint8Array} iv - The IV.
+ * @param {Uint8Array} ciphertext - The ciphertext.
+ * @returns {Promise<ArrayBuffer>} The decrypted bytes.
+ */
+async function decryptWithKey(key, iv, ciphertext) {
     try {
-
-        decrypted= await window.crypto.subtle.decrypt(
+        return await window.crypto.subtle.decrypt(
             { name: 'AES-CBC', iv: iv },
             key,
             ciphertext
@@ -61,25 +47,260 @@ async function decryptBase64Ciphertext(base64Ciphertext, password) {
     } catch {
         throw new Error("Decryption failed")
     }
+}
 
-    // Remove PKCS7 padding
-    const decryptedBytes = new Uint8Array(decrypted);
-    // const padLen = decryptedBytes[decryptedBytes.length - 1];
-    // const unpadded = decryptedBytes.slice(0, decryptedBytes.length - padLen);
+/**
+ * Decodes a base64-encoded ciphertext.
+ * The base64 data format: [1 byte n][n * ([16 bytes salt][48 bytes wrapped key])][16 bytes iv][ciphertext]
+ * Each of the n passwords unwraps the same content key, with the salt of its own.
+ *
+ * @param {string} base64Ciphertext - The base64-encoded ciphertext.
+ * @returns {{slots: {salt: Uint8Array, wrapped: Uint8Array}[], iv: Uint8Array, ciphertext: Uint8Array}}
+ */
+function parseCiphertext(base64Ciphertext) {
+    const binary = atob(base64Ciphertext);
+    const data = new Uint8Array(binary.length);
+    for (let i = 0; i < binary.length; i++) data[i] = binary.charCodeAt(i);
 
-    return new TextDecoder().decode(decryptedBytes);
+    const count = data[0] ?? 0;
+    const offset = 1 + count * 64;
+    if (data.length < offset + 32) throw new Error('Invalid ciphertext: too short');
+    const slots = [];
+    for (let i = 0; i < count; i++) {
+        const slot = data.slice(1 + i * 64, 1 + (i + 1) * 64);
+        slots.push({ salt: slot.slice(0, 16), wrapped: slot.slice(16) });
+    }
+    return { slots, iv: data.slice(offset, offset + 16), ciphertext: data.slice(offset + 16) };
 }
-const passwordForm = document.querySelector("#password-form");
-passwordForm.addEventListener("submit", async (e) => {
-    e.preventDefault();
-    const password = document.querySelector("#password").value;
-    const base64Ciphertext = document.querySelector("#ciphertext").innerText;
 
+/**
+ * Unwraps the content key of a slot with what is derived from its password, and decrypts with it.
+ *
+ * @param {Uint8Array} bits - What `deriveBits` derives from the password and the salt of the slot.
+ * @param {Uint8Array} wrapped - The wrapped key of the slot.
+ * @param {{iv: Uint8Array, ciphertext: Uint8Array}} payload - The parsed ciphertext.
+ * @returns {Promise<{plaintext: string, key: CryptoKey}>} The decrypted plaintext, and the key for assets.
+ */
+async function decryptSlot(bits, wrapped, payload) {
+    const wrapping = await window.crypto.subtle.importKey('raw', bits.slice(0, 32), { name: 'AES-CBC' }, false, ['decrypt']);
+    // a wrong password almost always fails on padding, otherwise on the content
+    const raw = await decryptWithKey(wrapping, bits.slice(32), wrapped);
+    const key = await window.crypto.subtle.importKey('raw', raw, { name: 'AES-CBC' }, false, ['decrypt']);
+    const decrypted = await decryptWithKey(key, payload.iv, payload.ciphertext);
     try {
-        const plaintext = await decryptBase64Ciphertext(base64Ciphertext, password);
-        document.querySelector("#plaintext").innerHTML = plaintext;
-        passwordForm.remove();
-    } catch (error) {
-        document.querySelector("#password-prompt").innerHTML = `Error: ${error.message}`;
+        return { plaintext: new TextDecoder('utf-8', { fatal: true }).decode(decrypted), key };
+    } catch {
+        throw new Error("Decryption failed")
+    }
+}
+
+/**
+ * Decodes a base64-encoded ciphertext and decrypts it using the provided password.
+ *
+ * @param {string} base64Ciphertext - The base64-encoded ciphertext.
+ * @param {string} password - The password for decryption.
+ * @returns {Promise<{plaintext: string, key: CryptoKey, keyId: string, bits: Uint8Array}>} The decrypted
+ * plaintext, the key for assets, and what is derived from the password with the id to remember it by.
+ */
+async function decryptBase64Ciphertext(base64Ciphertext, password) {
+    const payload = parseCiphertext(base64Ciphertext);
+    for (const slot of payload.slots) {
+        const bits = await deriveBits(password, slot.salt);
+        try {
+            return { ...await decryptSlot(bits, slot.wrapped, payload), keyId: toHex(slot.salt), bits };
+        } catch {
+            // try the next slot
+        }
+    }
+    throw new Error("Decryption failed");
+}
+
+const REMEMBERED_KEY_PREFIX = "mkcontent-key-";
+
+/**
+ * @param {Uint8Array} bytes
+ * @returns {string} The bytes in hex.
+ */
+function toHex(bytes) {
+    return Array.from(bytes, b => b.toString(16).padStart(2, "0")).join("");
+}
+
+/**
+ * Key ids of passwords which may be remembered for an encrypted element, embedded by mkcontent.
+ *
+ * @param {Element} element - The element containing the ciphertext.
+ * @returns {string[]} The key ids.
+ */
+function keyIdsOf(element) {
+    return (element.dataset.keyIds ?? "").split(" ").filter(id => id !== "");
+}
+
+/**
+ * Remembers what is derived from a password for the session, or on the device if `persistent`,
+ * so that blogs sharing the key id unlock without the password.
+ *
+ * @param {string[]} keyIds - The key ids which may be remembered.
+ * @param {{keyId: string, bits: Uint8Array}} result - The result of `decryptBase64Ciphertext`.
+ * @param {boolean} persistent - Whether to remember it after the session.
+ */
+function rememberKey(keyIds, { keyId, bits }, persistent) {
+    if (!keyIds.includes(keyId)) return;
+    const value = btoa(String.fromCharCode(...bits));
+    try {
+        sessionStorage.setItem(REMEMBERED_KEY_PREFIX + keyId, value);
+        if (persistent) localStorage.setItem(REMEMBERED_KEY_PREFIX + keyId, value);
+    } catch {
+        // storage may be disabled
+    }
+}
+
+/**
+ * Decrypts with a remembered key if any of the key ids is remembered, forgetting those which fail.
+ *
+ * @param {string} base64Ciphertext - The base64-encoded ciphertext.
+ * @param {string[]} keyIds - The key ids which may be remembered.
+ * @returns {Promise<{plaintext: string, key: CryptoKey} | null>} The decrypted plaintext and the key for assets.
+ */
+async function decryptRemembered(base64Ciphertext, keyIds) {
+    if (keyIds.length === 0) return null;
+    const payload = parseCiphertext(base64Ciphertext);
+    for (const slot of payload.slots) {
+        const keyId = toHex(slot.salt);
+        if (!keyIds.includes(keyId)) continue;
+        let stored = null;
+        try {
+            stored = sessionStorage.getItem(REMEMBERED_KEY_PREFIX + keyId) ?? localStorage.getItem(REMEMBERED_KEY_PREFIX + keyId);
+        } catch {
+            return null;
+        }
+        if (stored === null) continue;
+        try {
+            const bits = Uint8Array.from(atob(stored), c => c.charCodeAt(0));
+            return await decryptSlot(bits, slot.wrapped, payload);
+        } catch {
+            sessionStorage.removeItem(REMEMBERED_KEY_PREFIX + keyId);
+            localStorage.removeItem(REMEMBERED_KEY_PREFIX + keyId);
+        }
+    }
+    return null;
+}
+
+/**
+ * Fetches encrypted assets referred by `data-encrypted-src` and `data-encrypted-href`,
+ * and points `src` and `href` to blob URLs of them.
+ * The asset format: [16 bytes iv][ciphertext]
+ *
+ * @param {Element} root - The element containing the decrypted blog.
+ * @param {CryptoKey} key - The key the blog is decrypted with.
+ */
+async function decryptAssets(root, key) {
+    const elements = root.querySelectorAll("[data-encrypted-src], [data-encrypted-href]");
+    await Promise.all(Array.from(elements, async (element) => {
+        const attribute = element.hasAttribute("data-encrypted-src") ? "src" : "href";
+        try {
+            const response = await fetch(element.getAttribute(`data-encrypted-${attribute}`));
+            const data = new Uint8Array(await response.arrayBuffer());
+            const decrypted = await decryptWithKey(key, data.slice(0, 16), data.slice(16));
+            element.setAttribute(attribute, URL.createObjectURL(new Blob([decrypted])));
+        } catch (error) {
+            console.error(`Cannot load encrypted asset: ${error.message}`);
+        }
+    }));
+}
+
+/**
+ * Restores metadata of the blog hidden from the public, which is encrypted along with it.
+ *
+ * @param {Element} root - The element containing the decrypted blog.
+ */
+function restorePrivateMetadata(root) {
+    const element = root.querySelector("#private-metadata");
+    if (element === null) return;
+    const { title, lang, tags } = JSON.parse(element.textContent);
+    element.remove();
+
+    document.title = title;
+    document.querySelectorAll("[data-title]").forEach($e => $e.textContent = title);
+    document.querySelector("#blog").lang = lang;
+    if (tags.length === 0) return;
+    const container = document.createElement("div");
+    container.className = "tag-container";
+    container.innerHTML = '<i class="fa-solid fa-tags"></i>';
+    for (const tag of tags) {
+        // tags without public blogs have no page
+        const $tag = document.createElement(tag.url ? "a" : "span");
+        $tag.className = "tag";
+        if (tag.url) $tag.href = tag.url;
+        if (tag.color) $tag.style.setProperty("--tag-color", tag.color);
+        if (tag.description) $tag.title = tag.description;
+        $tag.innerHTML = "<b>#</b> ";
+        $tag.append(tag.name);
+        container.append(" ", $tag);
     }
-})
\ No newline at end of file
+    document.querySelector("#blog .metadata .date").after(container);
+}
+
+/**
+ * Binds unlock forms of secret sections, which are encrypted apart from the rest of the blog, and
+ * unlocks those with remembered keys. Their data format is the same as the blog's.
+ *
+ * @param {Element | Document} root - The element containing secret sections.
+ * @param {CryptoKey} [key] - The key of the blog with password, which its assets are encrypted with.
+ * Assets only used in secret sections of a public blog are encrypted with the key of the section.
+ */
+function bindSecretSections(root, key) {
+    root.querySelectorAll(".secret").forEach(async (section) => {
+        const form = section.querySelector(".secret-form");
+        if (form === null) return;
+        const ciphertextElement = section.querySelector(".secret-ciphertext");
+        const keyIds = keyIdsOf(ciphertextElement);
+        const unlock = async ({ plaintext, key: sectionKey }) => {
+            section.innerHTML = plaintext;
+            section.classList.add("unlocked");
+            await decryptAssets(section, key ?? sectionKey);
+        };
+        form.addEventListener("submit", async (e) => {
+            e.preventDefault();
+            const password = form.querySelector(".secret-password").value;
+            try {
+                const result = await decryptBase64Ciphertext(ciphertextElement.innerText, password);
+                rememberKey(keyIds, result, form.querySelector(".secret-remember")?.checked ?? false);
+                await unlock(result);
+            } catch (error) {
+                form.querySelector(".secret-prompt").innerHTML = `Error: ${error.message}`;
+            }
+        });
+        const remembered = await decryptRemembered(ciphertextElement.innerText, keyIds).catch(() => null);
+        if (remembered) await unlock(remembered);
+    });
+}
+
+const passwordForm = document.querySelector("#password-form");
+if (passwordForm) {
+    const ciphertextElement = document.querySelector("#ciphertext");
+    const keyIds = keyIdsOf(ciphertextElement);
+    const unlock = async ({ plaintext, key }) => {
+        const root = document.querySelector("#plaintext");
+        root.innerHTML = plaintext;
+        restorePrivateMetadata(root);
+        bindSecretSections(root, key);
+        passwordForm.remove();
+        await decryptAssets(root, key);
+    };
+    passwordForm.addEventListener("submit", async (e) => {
+        e.preventDefault();
+        const password = document.querySelector("#password").value;
+
+        try {
+            const result = await decryptBase64Ciphertext(ciphertextElement.innerText, password);
+            rememberKey(keyIds, result, document.querySelector("#remember")?.checked ?? false);
+            await unlock(result);
+        } catch (error) {
+            document.querySelector("#password-prompt").innerHTML = `Error: ${error.message}`;
+        }
+    });
+    decryptRemembered(ciphertextElement.innerText, keyIds).catch(() => null).then(async (remembered) => {
+        if (remembered) await unlock(remembered);
+    });
+}
+bindSecretSections(document);
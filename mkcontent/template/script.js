@@ -28,7 +28,9 @@ const refreshImageContent = () => {
     setTimeout(() => {
         viewerImage.style.transition = "transform 200ms ease-out";
     })
-    viewerCaption.innerText = blog_imgs[currentViewingId].alt;
+    const img = blog_imgs[currentViewingId];
+    // numbered caption of figures
+    viewerCaption.innerText = img.closest("figure")?.querySelector("figcaption")?.innerText ?? img.alt;
     viewerProgress.innerText = `${currentViewingId + 1}/${blog_imgs.length}`
 }
 
@@ -1,19 +1,14 @@
-export interface BlogInfo {
-    password: boolean,
-    hint: string | null,
-    title: string;
-    language: string;
-    license: string | null,
-    tags: string[],
+import type { Blog } from './manifest';
+
+/// Manifests and API files are declared by `mkcontent gen-types`
+export type * from './manifest';
+
+/// Blog metadata with dates parsed
+export interface BlogInfo extends Omit<Blog, "created" | "modified"> {
     created: Date;
     modified: Date;
-    preview: string | null,
 }
 
-export interface BlogManifest {
-    /// A map of blog id to blog metadata
-    blogs: Record<string, BlogInfo>,
-
-    /// A map of tags and blogs with the tags
-    tags: Record<string, string[]>,
-}
\ No newline at end of file
+export function parseBlog(blog: Blog): BlogInfo {
+    return { ...blog, created: new Date(blog.created), modified: new Date(blog.modified) };
+}
@@ -0,0 +1,97 @@
+// Generated by `mkcontent gen-types` from the Rust definitions. Do not edit.
+
+export type BlogManifest = { version: number, 
+/**
+ * Blogs keyed by id, ordered so that identical content yields identical manifests.
+ */
+blogs: Record<string, Blog>, tags: Record<string, Array<string>>, series: Record<string, Array<string>>, 
+/**
+ * Canonical tags declared in `tags.toml`.
+ */
+tag_metadata: Record<string, TagMetadata>, };
+
+export type Blog = { password: boolean, hint: string | null, title: string, language: Language, license: License | null, tags: Array<string>, 
+/**
+ * Name of the multi-part series the blog belongs to.
+ */
+series: string | null, 
+/**
+ * Position in the series, blogs without it are ordered by creation time.
+ */
+series_order: number | null, 
+/**
+ * RFC 3339 date.
+ */
+created: string, 
+/**
+ * RFC 3339 date.
+ */
+modified: string, preview: string | null, navigation: Navigation, };
+
+export type Language = string;
+
+export type License = "CC BY 4.0" | "CC BY-NC 4.0" | "CC BY-NC-ND 4.0" | "CC BY-NC-SA 4.0" | "CC BY-ND 4.0" | "CC BY-SA 4.0" | "CC0 1.0";
+
+export type Navigation = { 
+/**
+ * The previous public blog in chronological order.
+ */
+prev: string | null, 
+/**
+ * The next public blog in chronological order.
+ */
+next: string | null, 
+/**
+ * Public blogs sharing most tags with the blog.
+ */
+related: Array<string>, };
+
+export type TagMetadata = { 
+/**
+ * Name shown to readers, defaults to the canonical tag.
+ */
+name: string | null, description: string | null, 
+/**
+ * Any CSS color.
+ */
+color: string | null, 
+/**
+ * Other spellings normalized to the canonical tag.
+ */
+aliases: Array<string>, };
+
+export type ShardIndex = { page_size: number, blogs: ListingIndex, tags: Record<string, ListingIndex>, };
+
+export type ListingIndex = { 
+/**
+ * Directory of page files relative to the api directory.
+ */
+path: string, total: number, pages: number, };
+
+export type BlogPage = { 
+/**
+ * Page number, starting from 1.
+ */
+page: number, pages: number, blogs: Array<ListedBlog>, };
+
+export type ListedBlog = { id: string, password: boolean, hint: string | null, title: string, language: Language, license: License | null, tags: Array<string>, 
+/**
+ * Name of the multi-part series the blog belongs to.
+ */
+series: string | null, 
+/**
+ * Position in the series, blogs without it are ordered by creation time.
+ */
+series_order: number | null, 
+/**
+ * RFC 3339 date.
+ */
+created: string, 
+/**
+ * RFC 3339 date.
+ */
+modified: string, preview: string | null, navigation: Navigation, };
+
+export type SiteManifest = { version: number, sites: Record<string, Site>, };
+
+export type Site = { name: string, introduction: string, };
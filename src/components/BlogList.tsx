@@ -1,5 +1,5 @@
 import { useEffect, useState } from 'react';
-import { BlogInfo, BlogManifest } from '../api/blog';
+import { BlogInfo, BlogManifest, parseBlog } from '../api/blog';
 import Tag from './Tag';
 
 function BlogEntry({id, blog}: {id: string, blog: BlogInfo}) {
@@ -60,17 +60,14 @@ export default function BlogList({
     setDescent: ((descent: boolean) => void) | undefined,
     paging: boolean
 }) {
-    const [blogManifest, setBlogManifest] = useState<BlogManifest>({"blogs": {}, "tags": {}});
+    const [blogManifest, setBlogManifest] = useState<{blogs: Record<string, BlogInfo>, tags: BlogManifest["tags"]}>({"blogs": {}, "tags": {}});
     useEffect(() => {
         fetch('/api/blog-manifest.json')
             .then(res => res.json())
             .then((data: BlogManifest) => {
                 // Convert string dates to Date objects
-                Object.values(data.blogs).forEach(blog => {
-                    blog.created = new Date(blog.created);
-                    blog.modified = new Date(blog.modified);
-                });
-                setBlogManifest(data);
+                const blogs = Object.fromEntries(Object.entries(data.blogs).map(([id, blog]) => [id, parseBlog(blog)]));
+                setBlogManifest({ blogs, tags: data.tags });
             });
     }, []);
     const filteredBlogs = Object.entries(blogManifest.blogs)